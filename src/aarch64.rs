@@ -0,0 +1,297 @@
+/// Analyzes a subroutine and returns all the `BL` and `B` (including conditional branches)
+/// instructions in it, plus whether this function performs an indirect function call or not
+///
+/// Like in the Thumb analysis the targets are offsets relative to the start of the subroutine
+// NOTE all A64 instructions are 32-bit wide so, unlike Thumb, we don't need to decode the
+// instructions we are not interested in
+// Reference: Arm Architecture Reference Manual for A-profile architecture (ARM DDI 0487)
+pub fn analyze(bytes: &[u8]) -> (Vec<i64>, Vec<i64>, bool, bool, Option<u64>) {
+    const SP: u32 = 0b11111;
+
+    // we want to know if any of the instructions modifies the SP (stack pointer). These are the
+    // instructions LLVM uses to allocate stack frames:
+    // - d10083ff        sub     sp, sp, #32
+    // - d14007ff        sub     sp, sp, #1, lsl #12
+    // - a9bf7bfd        stp     x29, x30, [sp, #-16]!
+    // - 6dbe23e9        stp     d9, d8, [sp, #-32]!
+    // - f81f0ffe        str     x30, [sp, #-16]!
+    let mut modifies_sp = false;
+
+    // like in the Thumb analysis we give up computing the stack usage if we encounter conditionals
+    // or loops
+    let mut stack = Some(0);
+
+    let mut bls = vec![];
+    let mut bs = vec![];
+    let mut indirect = false;
+    for (word, i) in bytes.chunks_exact(4).zip(0i64..) {
+        let word = u32::from(word[0])
+            | u32::from(word[1]) << 8
+            | u32::from(word[2]) << 16
+            | u32::from(word[3]) << 24;
+        let pc = 4 * i;
+
+        let rd = word & 0b11111;
+        let rn = (word >> 5) & 0b11111;
+
+        if matches(word, BL) {
+            // BL
+            bls.push(pc + sign_extend((word & 0x03ff_ffff) << 2, 28));
+        } else if matches(word, B) {
+            // B
+            let target = pc + sign_extend((word & 0x03ff_ffff) << 2, 28);
+
+            if target >= 0 && (target as usize) < bytes.len() {
+                // this is an `if` or `loop`; give up the stack usage analysis
+                stack = None;
+            }
+
+            bs.push(target);
+        } else if matches(word, B_COND) || matches(word, CBZ) {
+            // B.cond, CBZ, CBNZ
+            let target = pc + sign_extend((word >> 5 & 0x7_ffff) << 2, 21);
+
+            if target >= 0 && (target as usize) < bytes.len() {
+                stack = None;
+            }
+
+            bs.push(target);
+        } else if matches(word, TBZ) {
+            // TBZ, TBNZ
+            let target = pc + sign_extend((word >> 5 & 0x3fff) << 2, 16);
+
+            if target >= 0 && (target as usize) < bytes.len() {
+                stack = None;
+            }
+
+            bs.push(target);
+        } else if matches(word, BLR) {
+            // BLR, BLRAA, BLRAAZ, BLRAB, BLRABZ
+            indirect = true;
+        } else if matches(word, BR) {
+            // BR, BRAA, BRAAZ, BRAB, BRABZ
+            // this is either an indirect tail call or a jump table
+            indirect = true;
+        } else if matches(word, RET) {
+            // RET, RETAA, RETAB
+            continue;
+        } else if matches(word, SUB_IMM) {
+            // SUB (immediate) - 64-bit
+            if rd == SP && rn == SP {
+                modifies_sp = true;
+
+                let sh = (word >> 22) & 1;
+                let imm12 = (word >> 10) & 0xfff;
+
+                if let Some(stack) = stack.as_mut() {
+                    *stack += u64::from(imm12 << (12 * sh));
+                }
+            }
+        } else if matches(word, SUB_EXT) {
+            // SUB (extended register) - 64-bit
+            if rd == SP && rn == SP {
+                // e.g. `alloca` with a dynamic size
+                modifies_sp = true;
+                stack = None;
+            }
+        } else if matches(word, STP_PRE) {
+            // STP (pre-index), including the SIMD&FP variant
+            let opc = word >> 30;
+            let v = (word >> 26) & 1 == 1;
+            let imm7 = sign_extend((word >> 15) & 0x7f, 7);
+
+            if rn == SP && imm7 < 0 {
+                modifies_sp = true;
+
+                // `W`, `X` registers or `S`, `D`, `Q` registers
+                let scale = if v { 4 << opc } else { 4 << (opc >> 1) };
+
+                if let Some(stack) = stack.as_mut() {
+                    *stack += (-imm7 * scale) as u64;
+                }
+            }
+        } else if matches(word, STR_PRE) {
+            // STR (immediate, pre-index), including the SIMD&FP variant
+            let imm9 = sign_extend((word >> 12) & 0x1ff, 9);
+
+            if rn == SP && imm9 < 0 {
+                modifies_sp = true;
+
+                if let Some(stack) = stack.as_mut() {
+                    *stack += (-imm9) as u64;
+                }
+            }
+        } else {
+            // some other instruction
+            continue;
+        }
+    }
+
+    (bls, bs, indirect, modifies_sp, stack)
+}
+
+// the encodings we decode as `(mask, value)` pairs; these are computed at compile time so decoding
+// an instruction is a few bitwise operations
+// BL
+const BL: (u32, u32) = pattern("0b100101_xxxxxxxxxxxxxxxxxxxxxxxxxx");
+// B
+const B: (u32, u32) = pattern("0b000101_xxxxxxxxxxxxxxxxxxxxxxxxxx");
+// B.cond
+const B_COND: (u32, u32) = pattern("0b01010100_xxxxxxxxxxxxxxxxxxx_0_xxxx");
+// CBZ, CBNZ
+const CBZ: (u32, u32) = pattern("0bx_011010_x_xxxxxxxxxxxxxxxxxxx_xxxxx");
+// TBZ, TBNZ
+const TBZ: (u32, u32) = pattern("0bx_011011_x_xxxxx_xxxxxxxxxxxxxx_xxxxx");
+// BLR, BLRAA, BLRAAZ, BLRAB, BLRABZ
+const BLR: (u32, u32) = pattern("0b1101011_x_0_01_11111_0000_x_x_xxxxx_xxxxx");
+// BR, BRAA, BRAAZ, BRAB, BRABZ
+const BR: (u32, u32) = pattern("0b1101011_x_0_00_11111_0000_x_x_xxxxx_xxxxx");
+// RET, RETAA, RETAB
+const RET: (u32, u32) = pattern("0b1101011_0_0_10_11111_0000_x_x_xxxxx_xxxxx");
+// SUB (immediate) - 64-bit
+const SUB_IMM: (u32, u32) = pattern("0b1_1_0_100010_x_xxxxxxxxxxxx_xxxxx_xxxxx");
+// SUB (extended register) - 64-bit
+const SUB_EXT: (u32, u32) = pattern("0b1_1_0_01011_00_1_xxxxx_xxx_xxx_xxxxx_xxxxx");
+// STP (pre-index), including the SIMD&FP variant
+const STP_PRE: (u32, u32) = pattern("0bxx_101_x_011_0_xxxxxxx_xxxxx_xxxxx_xxxxx");
+// STR (immediate, pre-index), including the SIMD&FP variant
+const STR_PRE: (u32, u32) = pattern("0bxx_111_x_00_x0_0_xxxxxxxxx_11_xxxxx_xxxxx");
+
+fn matches(word: u32, (mask, value): (u32, u32)) -> bool {
+    word & mask == value
+}
+
+// turns a pattern like `0b100101_xxxx..` into a `(mask, value)` pair; `x` bits are "don't care"
+const fn pattern(pattern: &str) -> (u32, u32) {
+    let bytes = pattern.as_bytes();
+    assert!(bytes.len() > 2 && bytes[0] == b'0' && bytes[1] == b'b');
+
+    let (mut mask, mut value, mut nbits) = (0, 0, 0);
+    let mut i = 2;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => {}
+            b'0' => {
+                mask = mask << 1 | 1;
+                value <<= 1;
+                nbits += 1;
+            }
+            b'1' => {
+                mask = mask << 1 | 1;
+                value = value << 1 | 1;
+                nbits += 1;
+            }
+            b'x' => {
+                mask <<= 1;
+                value <<= 1;
+                nbits += 1;
+            }
+            _ => panic!("BUG: invalid character in pattern"),
+        }
+
+        i += 1;
+    }
+    assert!(nbits == 32, "BUG: patterns must be 32 bits long");
+
+    (mask, value)
+}
+
+fn sign_extend(x: u32, nbits: u32) -> i64 {
+    let shift = 32 - nbits;
+    i64::from((x << shift) as i32 >> shift)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pattern() {
+        assert_eq!(
+            super::pattern("0b1101011_x_0_01_11111_0000_x_x_xxxxx_xxxxx"),
+            (0xfeff_f000, 0xd63f_0000)
+        );
+        assert_eq!(super::BL, (0xfc00_0000, 0x9400_0000));
+    }
+
+    #[test]
+    fn sanity() {
+        // 94000040        bl      #256
+        assert_eq!(super::analyze(&[0x40, 0x00, 0x00, 0x94]).0, vec![256]);
+
+        // 17fffff8        b       #-32
+        assert_eq!(
+            super::analyze(&[0xf8, 0xff, 0xff, 0x17]),
+            (vec![], vec![-32], false, false, Some(0))
+        );
+
+        // d503201f        nop
+        // 54000041        b.ne    #8
+        // b4000080        cbz     x0, #16
+        // 37180081        tbnz    w1, #3, #16
+        assert_eq!(
+            super::analyze(&[
+                0x1f, 0x20, 0x03, 0xd5, 0x41, 0x00, 0x00, 0x54, 0x80, 0x00, 0x00, 0xb4, 0x81, 0x00,
+                0x18, 0x37
+            ]),
+            (vec![], vec![4 + 8, 8 + 16, 12 + 16], false, false, None)
+        );
+
+        // d63f0100        blr     x8
+        assert!(super::analyze(&[0x00, 0x01, 0x3f, 0xd6]).2);
+
+        // d61f0200        br      x16
+        assert!(super::analyze(&[0x00, 0x02, 0x1f, 0xd6]).2);
+
+        // d65f03c0        ret
+        assert_eq!(
+            super::analyze(&[0xc0, 0x03, 0x5f, 0xd6]),
+            (vec![], vec![], false, false, Some(0))
+        );
+    }
+
+    #[test]
+    fn modifies_sp() {
+        // d503201f        nop
+        let nop = super::analyze(&[0x1f, 0x20, 0x03, 0xd5]);
+        assert!(!nop.3);
+        assert_eq!(nop.4, Some(0));
+
+        // d10083ff        sub     sp, sp, #32
+        let sub = super::analyze(&[0xff, 0x83, 0x00, 0xd1]);
+        assert!(sub.3);
+        assert_eq!(sub.4, Some(32));
+
+        // d14007ff        sub     sp, sp, #1, lsl #12
+        let sub_lsl = super::analyze(&[0xff, 0x07, 0x40, 0xd1]);
+        assert!(sub_lsl.3);
+        assert_eq!(sub_lsl.4, Some(4096));
+
+        // a9bf7bfd        stp     x29, x30, [sp, #-16]!
+        let stp = super::analyze(&[0xfd, 0x7b, 0xbf, 0xa9]);
+        assert!(stp.3);
+        assert_eq!(stp.4, Some(16));
+
+        // 6dbe23e9        stp     d9, d8, [sp, #-32]!
+        let stp_d = super::analyze(&[0xe9, 0x23, 0xbe, 0x6d]);
+        assert!(stp_d.3);
+        assert_eq!(stp_d.4, Some(32));
+
+        // f81f0ffe        str     x30, [sp, #-16]!
+        let str = super::analyze(&[0xfe, 0x0f, 0x1f, 0xf8]);
+        assert!(str.3);
+        assert_eq!(str.4, Some(16));
+
+        // cb2963ff        sub     sp, sp, x9
+        let alloca = super::analyze(&[0xff, 0x63, 0x29, 0xcb]);
+        assert!(alloca.3);
+        assert_eq!(alloca.4, None);
+
+        // a9017bfd        stp     x29, x30, [sp, #16]
+        // 910083ff        add     sp, sp, #32
+        // a8c17bfd        ldp     x29, x30, [sp], #16
+        let other = super::analyze(&[
+            0xfd, 0x7b, 0x01, 0xa9, 0xff, 0x83, 0x00, 0x91, 0xfd, 0x7b, 0xc1, 0xa8,
+        ]);
+        assert!(!other.3);
+        assert_eq!(other.4, Some(0));
+    }
+}
@@ -0,0 +1,189 @@
+//! Address-taken analysis of the machine code and data in the ELF file
+
+use std::collections::{BTreeMap, HashSet};
+
+use xmas_elf::{
+    header::Class,
+    sections::{SectionData, ShType, SHF_ALLOC, SHF_EXECINSTR},
+    ElfFile,
+};
+
+/// Returns the `functions` whose address appears as a value in the ELF file: in data (e.g. tables
+/// of `fn` pointers), in literal pools, in `movw` + `movt` pairs (Thumb) or as the addend of a
+/// relocation
+///
+/// `functions` maps the start address of each function to its size and to whether its machine
+/// code should be skipped (because we already have better information about it, e.g. from the
+/// LLVM-IR)
+pub(crate) fn scan(
+    elf: &ElfFile,
+    functions: &BTreeMap<u64, (u64, bool)>,
+    is_thumb: bool,
+) -> HashSet<u64> {
+    let word = match elf.header.pt1.class() {
+        Class::ThirtyTwo => 4,
+        _ => 8,
+    };
+
+    // whether `address` is in the machine code of a skipped function
+    let skip = |address: u64| {
+        functions
+            .range(..=address)
+            .next_back()
+            .map(|(start, (size, skip))| *skip && address < start + size)
+            .unwrap_or(false)
+    };
+
+    let mut values = vec![];
+    for sect in elf.section_iter() {
+        match sect.get_data(elf) {
+            Ok(SectionData::Rela32(relas)) => {
+                values.extend(
+                    relas
+                        .iter()
+                        .filter(|rela| !skip(u64::from(rela.get_offset())))
+                        .map(|rela| u64::from(rela.get_addend())),
+                );
+                continue;
+            }
+
+            Ok(SectionData::Rela64(relas)) => {
+                values.extend(
+                    relas
+                        .iter()
+                        .filter(|rela| !skip(rela.get_offset()))
+                        .map(|rela| rela.get_addend()),
+                );
+                continue;
+            }
+
+            _ => {}
+        }
+
+        if sect.flags() & SHF_ALLOC == 0 || sect.get_type() == Ok(ShType::NoBits) {
+            continue;
+        }
+
+        let data = sect.raw_data(elf);
+        let start = sect.address();
+        values.extend(
+            words(data, start, word)
+                .into_iter()
+                .filter(|(address, _)| !skip(*address))
+                .map(|(_, value)| value),
+        );
+
+        if is_thumb && sect.flags() & SHF_EXECINSTR != 0 {
+            values.extend(
+                movw_movt(data, start)
+                    .into_iter()
+                    .filter(|(address, _)| !skip(*address))
+                    .map(|(_, value)| value),
+            );
+        }
+    }
+
+    values
+        .into_iter()
+        .filter_map(|value| {
+            if functions.contains_key(&value) {
+                Some(value)
+            } else if is_thumb && functions.contains_key(&(value & !1)) {
+                // clear the thumb bit
+                Some(value & !1)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// aligned, little endian words in `data`, which starts at `start`; returns `(address, value)` pairs
+fn words(data: &[u8], start: u64, word: usize) -> Vec<(u64, u64)> {
+    let skip = (word - (start % word as u64) as usize) % word;
+
+    data.get(skip..)
+        .unwrap_or(&[])
+        .chunks_exact(word)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | u64::from(*byte));
+
+            (start + (skip + i * word) as u64, value)
+        })
+        .collect()
+}
+
+// values materialized by `movw rd, #lo` + `movt rd, #hi` pairs in the Thumb code `text`, which
+// starts at `start`; returns `(address of movt, value)` pairs
+//
+// NOTE every halfword is considered the start of an instruction; this may produce bogus values but
+// those rarely match the address of a function
+fn movw_movt(text: &[u8], start: u64) -> Vec<(u64, u64)> {
+    let halfwords = text
+        .chunks_exact(2)
+        .map(|hw| u16::from(hw[0]) | u16::from(hw[1]) << 8)
+        .collect::<Vec<_>>();
+
+    let mut lows = [None; 16]; // register -> last `movw` immediate
+    let mut values = vec![];
+    for (i, pair) in halfwords.windows(2).enumerate() {
+        let (first, second) = (pair[0], pair[1]);
+
+        if second & 0x8000 != 0 {
+            continue;
+        }
+
+        // encoding T3 of MOV (immediate) and encoding T1 of MOVT
+        let imm16 = u32::from(first & 0xf) << 12
+            | u32::from(first >> 10 & 1) << 11
+            | u32::from(second >> 12 & 0b111) << 8
+            | u32::from(second & 0xff);
+        let rd = usize::from(second >> 8 & 0xf);
+
+        match first & 0xfbf0 {
+            0xf240 => lows[rd] = Some(imm16),
+            0xf2c0 => {
+                if let Some(low) = lows[rd] {
+                    values.push((start + 2 * i as u64, u64::from(imm16 << 16 | low)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn movw_movt() {
+        let text = [
+            0x41, 0xf2, 0x35, 0x20, // movw r0, #0x1235
+            0x00, 0xbf, // nop
+            0xc0, 0xf6, 0x00, 0x00, // movt r0, #0x0800
+        ];
+
+        assert_eq!(
+            super::movw_movt(&text, 0x0800_0100),
+            vec![(0x0800_0106, 0x0800_1235)]
+        );
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            super::words(&[0xff, 0xff, 0x35, 0x12, 0x00, 0x08, 0x01], 0x2000_0002, 4),
+            vec![(0x2000_0004, 0x0800_1235)]
+        );
+
+        assert_eq!(
+            super::words(&[0x35, 0x12, 0x00, 0x08, 0, 0, 0, 0], 0x1000, 8),
+            vec![(0x1000, 0x0800_1235)]
+        );
+    }
+}
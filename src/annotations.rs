@@ -0,0 +1,295 @@
+//! User provided information that the analysis can't figure out on its own
+
+use std::collections::{HashMap, HashSet};
+
+use failure::format_err;
+use petgraph::graph::{Graph, NodeIndex};
+use serde::Deserialize;
+
+use crate::{find, Diagnostic, Edge, Node, Rule};
+
+/// Contents of an annotations file
+///
+/// ``` toml
+/// # the indirect function calls performed by `app::dispatch` only reach these functions
+/// [[calls]]
+/// caller = "app::dispatch"
+/// callees = ["app::on_rx", "app::on_tx"]
+///
+/// # all the calls through this function pointer type only reach these functions
+/// [[calls]]
+/// signature = "i32 (i32)*"
+/// callees = ["app::double"]
+///
+/// # stack usage of a function that has no stack usage information (e.g. a C function)
+/// [[stack]]
+/// function = "crc32"
+/// local = 48
+///
+/// # stack usage of an inline assembly block
+/// [[asm]]
+/// code = "push {r4, r5}"
+/// stack = 8
+///
+/// # `app::parse_expr` recurses at most 8 levels deep
+/// [[recursion]]
+/// functions = ["app::parse_expr"]
+/// depth = 8
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Annotations {
+    #[serde(default)]
+    pub calls: Vec<Calls>,
+    #[serde(default)]
+    pub stack: Vec<Stack>,
+    #[serde(default)]
+    pub asm: Vec<Asm>,
+    #[serde(default)]
+    pub recursion: Vec<Recursion>,
+}
+
+/// Callees of the indirect function calls performed by `caller` or of all the calls made through
+/// a function pointer / trait object of type `signature`
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Calls {
+    pub caller: Option<String>,
+    /// As it appears in the call graph (e.g. `i32 (i32)*`)
+    pub signature: Option<String>,
+    pub callees: Vec<String>,
+}
+
+/// Local stack usage of a function
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Stack {
+    pub function: String,
+    pub local: u64,
+}
+
+/// Stack usage of an `asm!` string; this is added to the local stack usage of the functions that
+/// contain it
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Asm {
+    pub code: String,
+    pub stack: u64,
+}
+
+/// Maximum number of frames of `functions` that can be on the stack at the same time
+///
+/// All the cycles of the call graph that contain these functions must go through at least one of
+/// them. Listing all the functions of a cycle bounds the number of frames of the whole cycle.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Recursion {
+    pub functions: Vec<String>,
+    pub depth: u64,
+}
+
+impl Annotations {
+    /// Parses the contents of an annotations file (TOML)
+    pub fn parse(toml: &str) -> Result<Self, failure::Error> {
+        let annotations: Annotations = toml::from_str(toml)?;
+
+        for calls in &annotations.calls {
+            match (&calls.caller, &calls.signature) {
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => {
+                    return Err(format_err!(
+                        "each `[[calls]]` entry must have either a `caller` or a `signature` \
+                         field, but not both"
+                    ));
+                }
+            }
+        }
+
+        for recursion in &annotations.recursion {
+            if recursion.depth == 0 {
+                return Err(format_err!(
+                    "the recursion depth of {:?} must be at least 1",
+                    recursion.functions
+                ));
+            }
+        }
+
+        Ok(annotations)
+    }
+
+    /// Whether `name` has been given a stack usage
+    pub(crate) fn has_stack(&self, name: &str) -> bool {
+        let demangled = rustc_demangle::demangle(name).to_string();
+
+        self.stack
+            .iter()
+            .any(|stack| crate::matches(name, &demangled, &stack.function))
+    }
+
+    pub(crate) fn asm(&self, code: &str) -> Option<u64> {
+        self.asm
+            .iter()
+            .find(|asm| asm.code == code)
+            .map(|asm| asm.stack)
+    }
+}
+
+// `[[calls]]` entries with their function names resolved to nodes of the call graph
+#[derive(Default)]
+pub(crate) struct Callees<'a> {
+    pub callers: HashMap<NodeIndex, HashSet<NodeIndex>>,
+    pub signatures: HashMap<&'a str, HashSet<NodeIndex>>,
+}
+
+impl<'a> Callees<'a> {
+    pub fn resolve(
+        annotations: &'a Annotations,
+        g: &Graph<Node, Edge>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Self {
+        let mut resolved = Callees::default();
+
+        for calls in &annotations.calls {
+            let callees = calls
+                .callees
+                .iter()
+                .filter_map(|callee| resolve(g, callee, diagnostics))
+                .collect::<HashSet<_>>();
+
+            if let Some(caller) = &calls.caller {
+                if let Some(caller) = resolve(g, caller, diagnostics) {
+                    resolved.callers.entry(caller).or_default().extend(callees);
+                }
+            } else if let Some(signature) = &calls.signature {
+                // the trailing `*` is optional
+                let signature = signature.trim_end_matches('*');
+
+                resolved
+                    .signatures
+                    .entry(signature)
+                    .or_default()
+                    .extend(callees);
+            }
+        }
+
+        resolved
+    }
+
+    // `signature` as it appears in the call graph (with or without the trailing `*`)
+    pub fn signature(&self, signature: &str) -> Option<&HashSet<NodeIndex>> {
+        self.signatures.get(signature.trim_end_matches('*'))
+    }
+}
+
+// looks up the node `name` refers to; reports an error if there's no such node or if the name is
+// ambiguous
+pub(crate) fn resolve(
+    g: &Graph<Node, Edge>,
+    name: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<NodeIndex> {
+    match &find(g, name)[..] {
+        [] => {
+            warning!(
+                diagnostics,
+                Rule::Annotations,
+                "annotations: function `{}` is not in the call graph; ignoring it",
+                name
+            );
+            None
+        }
+
+        [idx] => Some(*idx),
+
+        hits => {
+            let hits = hits.iter().map(|idx| &g[*idx].name).collect::<Vec<_>>();
+            error!(
+                diagnostics,
+                Rule::Annotations,
+                "annotations: multiple matches for `{}`: {:?}",
+                name,
+                hits
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Annotations, Asm, Calls, Recursion, Stack};
+
+    #[test]
+    fn parse() {
+        let annotations = Annotations::parse(
+            r#"
+[[calls]]
+caller = "app::dispatch"
+callees = ["app::on_rx", "app::on_tx"]
+
+[[calls]]
+signature = "i32 (i32)*"
+callees = ["app::double"]
+
+[[stack]]
+function = "crc32"
+local = 48
+
+[[asm]]
+code = "push {r4, r5}"
+stack = 8
+
+[[recursion]]
+functions = ["app::parse_expr"]
+depth = 8
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            annotations,
+            Annotations {
+                calls: vec![
+                    Calls {
+                        caller: Some("app::dispatch".to_owned()),
+                        signature: None,
+                        callees: vec!["app::on_rx".to_owned(), "app::on_tx".to_owned()],
+                    },
+                    Calls {
+                        caller: None,
+                        signature: Some("i32 (i32)*".to_owned()),
+                        callees: vec!["app::double".to_owned()],
+                    },
+                ],
+                stack: vec![Stack {
+                    function: "crc32".to_owned(),
+                    local: 48,
+                }],
+                asm: vec![Asm {
+                    code: "push {r4, r5}".to_owned(),
+                    stack: 8,
+                }],
+                recursion: vec![Recursion {
+                    functions: vec!["app::parse_expr".to_owned()],
+                    depth: 8,
+                }],
+            }
+        );
+
+        assert!(annotations.has_stack("crc32"));
+        assert_eq!(annotations.asm("push {r4, r5}"), Some(8));
+        assert_eq!(annotations.asm("bkpt"), None);
+
+        assert_eq!(Annotations::parse("").unwrap(), Annotations::default());
+
+        // `caller` xor `signature`
+        assert!(Annotations::parse("[[calls]]\ncallees = []").is_err());
+        assert!(Annotations::parse(
+            "[[calls]]\ncaller = \"foo\"\nsignature = \"void ()*\"\ncallees = []"
+        )
+        .is_err());
+
+        assert!(Annotations::parse("[[recursion]]\nfunctions = [\"foo\"]\ndepth = 0").is_err());
+        assert!(Annotations::parse("[[stack]]\nfunction = \"foo\"\nlocal = 8\nmax = 8").is_err());
+    }
+}
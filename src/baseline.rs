@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::{dehash, json::CallGraph, max, Diagnostic, Level, Max, Rule};
+
+/// Which differences between a baseline and a new run are considered errors
+///
+/// Differences that are not errors are reported as warnings
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    /// How much the max stack usage of a function can grow, in bytes
+    pub growth: u64,
+    /// New call edges are errors
+    pub new_edges: bool,
+    /// New cycles are errors
+    pub new_cycles: bool,
+    /// Max stack usages that went from exact to a lower bound are errors
+    pub lower_bounds: bool,
+}
+
+/// Compares a `new` run against an `old` one (the baseline)
+pub fn compare(old: &CallGraph, new: &CallGraph, thresholds: &Thresholds) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let old_keys = keys(old);
+    let new_keys = keys(new);
+
+    // max stack usage
+    let old_maxes = maxes(old, &old_keys);
+    for (key, new_max) in maxes(new, &new_keys) {
+        let old_max = if let Some(old_max) = old_maxes.get(&key) {
+            *old_max
+        } else {
+            // new function
+            continue;
+        };
+
+        if new_max.value() > old_max.value() {
+            let growth = new_max.value() - old_max.value();
+
+            diagnostics.push(Diagnostic {
+                level: if growth > thresholds.growth {
+                    Level::Error
+                } else {
+                    Level::Warning
+                },
+                rule: Rule::Baseline,
+                function: Some(key.clone()),
+                message: format!(
+                    "the max stack usage of `{}` grew by {} bytes (max {} -> max {})",
+                    key, growth, old_max, new_max
+                ),
+            });
+        }
+
+        if let (Max::Exact(_), Max::LowerBound(_)) = (old_max, new_max) {
+            diagnostics.push(Diagnostic {
+                level: if thresholds.lower_bounds {
+                    Level::Error
+                } else {
+                    Level::Warning
+                },
+                rule: Rule::Baseline,
+                function: Some(key.clone()),
+                message: format!(
+                    "the max stack usage of `{}` is no longer exact (max {} -> max {})",
+                    key, old_max, new_max
+                ),
+            });
+        }
+    }
+
+    // call edges
+    let old_edges = old
+        .edges
+        .iter()
+        .map(|edge| (&old_keys[edge.source], &old_keys[edge.target]))
+        .collect::<HashSet<_>>();
+    let mut new_edges = BTreeSet::new();
+    for edge in &new.edges {
+        let edge = (&new_keys[edge.source], &new_keys[edge.target]);
+
+        if !old_edges.contains(&edge) {
+            new_edges.insert(edge);
+        }
+    }
+
+    for (caller, callee) in new_edges {
+        diagnostics.push(Diagnostic {
+            level: if thresholds.new_edges {
+                Level::Error
+            } else {
+                Level::Warning
+            },
+            rule: Rule::Baseline,
+            function: Some(caller.clone()),
+            message: format!("new call edge: `{}` -> `{}`", caller, callee),
+        });
+    }
+
+    // cycles
+    let old_cycles = cycles(old, &old_keys);
+    for cycle in cycles(new, &new_keys) {
+        // a cycle that grew to include more functions is considered a new cycle
+        if !old_cycles.iter().any(|old| cycle.is_subset(old)) {
+            let members = cycle
+                .iter()
+                .map(|key| format!("`{}`", key))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            diagnostics.push(Diagnostic {
+                level: if thresholds.new_cycles {
+                    Level::Error
+                } else {
+                    Level::Warning
+                },
+                rule: Rule::Baseline,
+                function: cycle.iter().next().cloned(),
+                message: format!("new cycle: {}", members),
+            });
+        }
+    }
+
+    diagnostics
+}
+
+// Nodes are matched by name but the hash in symbol names can change between builds so we remove it
+fn keys(cg: &CallGraph) -> Vec<String> {
+    cg.nodes
+        .iter()
+        .map(|node| {
+            let demangled = rustc_demangle::demangle(&node.name).to_string();
+
+            if let Some(dehashed) = dehash(&demangled) {
+                return dehashed.to_owned();
+            }
+
+            demangled
+        })
+        .collect()
+}
+
+// Symbols that only differ in their hash are merged into a single entry
+fn maxes(cg: &CallGraph, keys: &[String]) -> BTreeMap<String, Max> {
+    let mut maxes = BTreeMap::new();
+
+    for node in &cg.nodes {
+        if let Some(node_max) = node.max {
+            maxes
+                .entry(keys[node.id].clone())
+                .and_modify(|m| *m = max(*m, node_max))
+                .or_insert(node_max);
+        }
+    }
+
+    maxes
+}
+
+fn cycles(cg: &CallGraph, keys: &[String]) -> Vec<BTreeSet<String>> {
+    cg.sccs
+        .iter()
+        .map(|scc| scc.iter().map(|id| keys[*id].clone()).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Thresholds;
+    use crate::{json::CallGraph, Level};
+
+    const OLD: &str = r#"{
+  "version": 1,
+  "nodes": [
+    { "id": 0, "name": "main", "demangled": "main", "local": { "kind": "exact", "value": 8 }, "max": { "kind": "exact", "value": 24 }, "max_callee": 1, "dashed": false },
+    { "id": 1, "name": "_ZN3app3foo17h3337355bfdc88d96E", "demangled": "app::foo", "local": { "kind": "exact", "value": 16 }, "max": { "kind": "exact", "value": 16 }, "max_callee": null, "dashed": false },
+    { "id": 2, "name": "_ZN3app3bar17h0123456789abcdefE", "demangled": "app::bar", "local": { "kind": "exact", "value": 0 }, "max": { "kind": "exact", "value": 0 }, "max_callee": null, "dashed": false }
+  ],
+  "edges": [{ "source": 0, "target": 1 }, { "source": 0, "target": 2 }],
+  "sccs": []
+}"#;
+
+    // `foo` grew; `bar` now calls `foo` and it calls itself; the hash of `foo` changed
+    const NEW: &str = r#"{
+  "version": 1,
+  "nodes": [
+    { "id": 0, "name": "main", "demangled": "main", "local": { "kind": "exact", "value": 8 }, "max": { "kind": "lower_bound", "value": 40 }, "max_callee": 1, "dashed": false },
+    { "id": 1, "name": "_ZN3app3foo17hfedcba9876543210E", "demangled": "app::foo", "local": { "kind": "exact", "value": 32 }, "max": { "kind": "lower_bound", "value": 32 }, "max_callee": null, "dashed": false },
+    { "id": 2, "name": "_ZN3app3bar17h0123456789abcdefE", "demangled": "app::bar", "local": { "kind": "exact", "value": 0 }, "max": { "kind": "lower_bound", "value": 32 }, "max_callee": 1, "dashed": false }
+  ],
+  "edges": [{ "source": 0, "target": 1 }, { "source": 0, "target": 2 }, { "source": 2, "target": 1 }, { "source": 1, "target": 1 }],
+  "sccs": [[1]]
+}"#;
+
+    #[test]
+    fn compare() {
+        let old = CallGraph::parse(OLD).unwrap();
+        let new = CallGraph::parse(NEW).unwrap();
+
+        let diagnostics = super::compare(&old, &new, &Thresholds::default());
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            messages,
+            vec![
+                "the max stack usage of `app::bar` grew by 32 bytes (max = 0 -> max >= 32)",
+                "the max stack usage of `app::bar` is no longer exact (max = 0 -> max >= 32)",
+                "the max stack usage of `app::foo` grew by 16 bytes (max = 16 -> max >= 32)",
+                "the max stack usage of `app::foo` is no longer exact (max = 16 -> max >= 32)",
+                "the max stack usage of `main` grew by 16 bytes (max = 24 -> max >= 40)",
+                "the max stack usage of `main` is no longer exact (max = 24 -> max >= 40)",
+                "new call edge: `app::bar` -> `app::foo`",
+                "new call edge: `app::foo` -> `app::foo`",
+                "new cycle: `app::foo`",
+            ]
+        );
+
+        // only growth is an error by default
+        assert!(diagnostics
+            .iter()
+            .all(|d| (d.level == Level::Error) == d.message.contains("grew")));
+
+        let lenient = Thresholds {
+            growth: 32,
+            ..Thresholds::default()
+        };
+        assert!(super::compare(&old, &new, &lenient)
+            .iter()
+            .all(|d| d.level == Level::Warning));
+    }
+}
@@ -0,0 +1,190 @@
+use failure::format_err;
+use petgraph::{
+    graph::{Graph, NodeIndex},
+    Direction,
+};
+
+use crate::{find, CallGraph, Diagnostic, Edge, Max, Node, Rule};
+
+/// Maximum stack usage allowed
+#[derive(Debug, Default, PartialEq)]
+pub struct Budgets<'a> {
+    // applies to all the roots of the call graph (nodes that have no callers)
+    pub global: Option<u64>,
+    // applies to specific functions; these override the global budget
+    pub functions: Vec<(&'a str, u64)>,
+}
+
+impl<'a> Budgets<'a> {
+    /// Parses a list of `BYTES` or `FUNCTION=BYTES` values
+    pub fn parse(values: impl Iterator<Item = &'a str>) -> Result<Self, failure::Error> {
+        let mut budgets = Budgets::default();
+
+        for value in values {
+            let (function, bytes) = if let Some(pos) = value.rfind('=') {
+                (Some(&value[..pos]), &value[pos + 1..])
+            } else {
+                (None, value)
+            };
+
+            let bytes = bytes
+                .parse()
+                .map_err(|_| format_err!("`{}` is not a valid stack budget", value))?;
+
+            if let Some(function) = function {
+                budgets.functions.push((function, bytes));
+            } else if budgets.global.is_some() {
+                return Err(format_err!(
+                    "the global stack budget was specified more than once"
+                ));
+            } else {
+                budgets.global = Some(bytes);
+            }
+        }
+
+        Ok(budgets)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none() && self.functions.is_empty()
+    }
+}
+
+/// What to do when the maximum stack usage of a function is a lower bound that's within budget
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowerBoundPolicy {
+    // the function passes the check
+    Pass,
+    // the function fails the check because we can't tell if it's within budget
+    Fail,
+}
+
+/// Checks the call graph against the stack `budgets`; returns one error per function that goes
+/// over its budget
+pub fn check(cg: &CallGraph, budgets: &Budgets, policy: LowerBoundPolicy) -> Vec<Diagnostic> {
+    let g = &cg.graph;
+    let mut diagnostics = vec![];
+    let mut checked = vec![];
+
+    for (name, budget) in &budgets.functions {
+        match &find(g, name)[..] {
+            [] => {
+                error!(
+                    diagnostics,
+                    Rule::Budget,
+                    "stack budget: function `{}` is not in the call graph",
+                    name
+                );
+            }
+
+            [idx] => {
+                check_node(g, *idx, *budget, policy, &mut diagnostics);
+                checked.push(*idx);
+            }
+
+            hits => {
+                let hits = hits.iter().map(|idx| &g[*idx].name).collect::<Vec<_>>();
+                error!(
+                    diagnostics,
+                    Rule::Budget,
+                    "stack budget: multiple matches for `{}`: {:?}",
+                    name,
+                    hits
+                );
+            }
+        }
+    }
+
+    if let Some(budget) = budgets.global {
+        for idx in g.node_indices() {
+            let is_root = g
+                .neighbors_directed(idx, Direction::Incoming)
+                .all(|caller| caller == idx);
+
+            if is_root && !checked.contains(&idx) {
+                check_node(g, idx, budget, policy, &mut diagnostics);
+            }
+        }
+    }
+
+    diagnostics
+}
+
+fn check_node(
+    g: &Graph<Node, Edge>,
+    idx: NodeIndex,
+    budget: u64,
+    policy: LowerBoundPolicy,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let node = &g[idx];
+
+    match node.max {
+        Some(Max::Exact(n)) if n > budget => {
+            error!(
+                diagnostics,
+                Rule::Budget,
+                function = node.name,
+                "`{}` uses {} bytes of stack; this exceeds its budget of {} bytes",
+                node.demangled,
+                n,
+                budget
+            );
+        }
+
+        Some(Max::LowerBound(n)) if n > budget => {
+            error!(
+                diagnostics,
+                Rule::Budget,
+                function = node.name,
+                "`{}` uses at least {} bytes of stack; this exceeds its budget of {} bytes",
+                node.demangled,
+                n,
+                budget
+            );
+        }
+
+        Some(Max::LowerBound(n)) if policy == LowerBoundPolicy::Fail => {
+            error!(
+                diagnostics,
+                Rule::Budget,
+                function = node.name,
+                "`{}` uses at least {} bytes of stack; it may exceed its budget of {} bytes",
+                node.demangled,
+                n,
+                budget
+            );
+        }
+
+        Some(_) => {}
+
+        None => {
+            error!(
+                diagnostics,
+                Rule::Budget,
+                function = node.name,
+                "the max stack usage of `{}` is unknown; can't check it against its budget",
+                node.demangled
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budgets;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Budgets::parse(vec!["1024", "main=512", "SysTick=128"].into_iter()).unwrap(),
+            Budgets {
+                global: Some(1024),
+                functions: vec![("main", 512), ("SysTick", 128)],
+            }
+        );
+
+        assert!(Budgets::parse(vec!["1024", "2048"].into_iter()).is_err());
+        assert!(Budgets::parse(vec!["main=lots"].into_iter()).is_err());
+    }
+}
@@ -0,0 +1,515 @@
+//! Stack usage and signatures of the `compiler-builtins` functions
+//!
+//! The `compiler-builtins` crate that ships with the toolchain is not compiled with
+//! `-Z emit-stack-sizes` so this information has to come from somewhere else.
+
+use std::collections::BTreeMap;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+use xmas_elf::{
+    sections::{SectionData, SHF_EXECINSTR},
+    symbol_table::{Binding, Entry, Type as SymbolType},
+    ElfFile,
+};
+
+use crate::{
+    ir::{FnSig, Type},
+    thumb::Tag,
+    Object, Target,
+};
+
+/// Contents of a builtins file
+///
+/// ``` toml
+/// # toolchain the stack usage was measured on
+/// toolchain = "1.33.0"
+///
+/// [[function]]
+/// name = "__aeabi_memcpy"
+/// local = 0
+/// signature = "void (void *, const void *, size_t)"
+///
+/// # `local` and `signature` are optional; functions that have no `signature` use a non-standard
+/// # calling convention and can't be called from Rust
+/// [[function]]
+/// name = "__aeabi_uldivmod"
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Builtins {
+    /// Version of the Rust toolchain the stack usage was measured on (e.g. `1.33.0`)
+    pub toolchain: String,
+    #[serde(default, rename = "function")]
+    pub functions: Vec<Builtin>,
+}
+
+/// A function of the `compiler-builtins` crate
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Builtin {
+    pub name: String,
+    /// Local stack usage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<u64>,
+    /// C signature (e.g. `float (float, float)`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Builtins {
+    /// Parses the contents of a builtins file (TOML)
+    pub fn parse(toml: &str) -> Result<Self, failure::Error> {
+        let builtins: Builtins = toml::from_str(toml)?;
+
+        for function in &builtins.functions {
+            if let Some(signature) = &function.signature {
+                if self::signature(signature, 32).is_none() {
+                    return Err(format_err!(
+                        "couldn't parse the signature of `{}`: `{}`",
+                        function.name,
+                        signature
+                    ));
+                }
+            }
+        }
+
+        Ok(builtins)
+    }
+
+    /// The builtins file that ships with this tool for `target`; it's empty if there's none
+    pub fn bundled(target: &str) -> Self {
+        let toml = match target {
+            "thumbv6m-none-eabi" => include_str!("../builtins/thumbv6m-none-eabi.toml"),
+            "thumbv7m-none-eabi" => include_str!("../builtins/thumbv7m-none-eabi.toml"),
+            "thumbv7em-none-eabi" => include_str!("../builtins/thumbv7em-none-eabi.toml"),
+            "thumbv7em-none-eabihf" => include_str!("../builtins/thumbv7em-none-eabihf.toml"),
+            _ => return Builtins::default(),
+        };
+
+        Builtins::parse(toml).expect("BUG: invalid bundled builtins file")
+    }
+
+    /// The signatures of the functions in the bundled builtins files, without stack usage
+    /// information
+    ///
+    /// These are C (and AEABI) signatures so they don't depend on the target; they are used for
+    /// the functions that are missing from the builtins file of the target (e.g. because this tool
+    /// ships no builtins file for it)
+    pub fn signatures() -> Self {
+        let mut builtins = Builtins::bundled("thumbv7m-none-eabi");
+
+        for function in &mut builtins.functions {
+            function.local = None;
+        }
+
+        builtins
+    }
+
+    /// Whether the stack usage in this file applies to programs compiled with `toolchain` (e.g.
+    /// `1.33.0-nightly`)
+    ///
+    /// Only the `major.minor.patch` part of the versions is compared; `-nightly` and `-beta.N`
+    /// toolchains report the version of the stable release they will become
+    pub fn is_fresh(&self, toolchain: &str) -> bool {
+        release(toolchain) == release(&self.toolchain)
+    }
+
+    /// Measures the local stack usage of the functions defined in the `compiler-builtins` object
+    /// files found in `objects` (see `sysroot_objects`) using the analysis of the machine code
+    ///
+    /// `toolchain` is the version of the toolchain the objects come from. Signatures are taken
+    /// from `previous` (or, for functions it doesn't list, from `Builtins::signatures`), as they
+    /// can't be recovered from the machine code.
+    pub fn measure(
+        target: &str,
+        objects: &[Object],
+        toolchain: &str,
+        previous: &Builtins,
+    ) -> Result<Self, failure::Error> {
+        let target_ = Target::from_triple(target);
+
+        if !target_.has_decoder() {
+            return Err(format_err!(
+                "the machine code of `{}` programs can't be analyzed",
+                target
+            ));
+        }
+
+        let mut locals = BTreeMap::new();
+        for object in objects {
+            if !object.origin.starts_with("libcompiler_builtins") {
+                continue;
+            }
+
+            let elf = ElfFile::new(&object.bytes).map_err(failure::err_msg)?;
+
+            for (name, local) in measure_object(&elf, target_)? {
+                locals.insert(name, local);
+            }
+        }
+
+        let mut functions = previous
+            .functions
+            .iter()
+            .map(|function| Builtin {
+                name: function.name.clone(),
+                local: locals.remove(&function.name).and_then(|local| local),
+                signature: function.signature.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let signatures = Builtins::signatures();
+        functions.extend(locals.into_iter().map(|(name, local)| {
+            Builtin {
+                signature: signatures
+                    .get(&name)
+                    .and_then(|builtin| builtin.signature.clone()),
+                name,
+                local,
+            }
+        }));
+
+        Ok(Builtins {
+            toolchain: toolchain.to_owned(),
+            functions,
+        })
+    }
+
+    /// Serializes the builtins back into a builtins file (TOML)
+    pub fn to_toml(&self) -> Result<String, failure::Error> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Builtin> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+// strips the pre-release and build metadata from a version (`1.33.0-nightly` -> `1.33.0`)
+fn release(version: &str) -> &str {
+    version
+        .split(&['-', '+'][..])
+        .next()
+        .unwrap_or(version)
+        .trim()
+}
+
+// local stack usage of the global functions defined in the object file `elf`; `None` if the
+// analysis couldn't compute it
+fn measure_object(
+    elf: &ElfFile,
+    target_: Target,
+) -> Result<Vec<(String, Option<u64>)>, failure::Error> {
+    // (name, section index, value, size, is_function)
+    let mut symbols = vec![];
+    if let Some(sect) = elf.find_section_by_name(".symtab") {
+        match sect.get_data(elf).map_err(failure::err_msg)? {
+            SectionData::SymbolTable32(entries) => {
+                symbols.extend(entries.iter().filter_map(|entry| symbol(elf, entry)))
+            }
+
+            SectionData::SymbolTable64(entries) => {
+                symbols.extend(entries.iter().filter_map(|entry| symbol(elf, entry)))
+            }
+
+            _ => {}
+        }
+    }
+
+    let mut locals = vec![];
+    for (name, shndx, value, size, is_function) in &symbols {
+        if !is_function {
+            continue;
+        }
+
+        let sect = elf.section_header(*shndx).map_err(failure::err_msg)?;
+        if sect.flags() & SHF_EXECINSTR == 0 {
+            continue;
+        }
+
+        // in object files symbol values are offsets within their section
+        let mut tags = vec![];
+        if target_.is_thumb() {
+            tags = symbols
+                .iter()
+                .filter(|(name, tag_shndx, ..)| {
+                    tag_shndx == shndx && (name.starts_with("$d") || name.starts_with("$t"))
+                })
+                .map(|(name, _, value, ..)| {
+                    let tag = if name.starts_with("$d") {
+                        Tag::Data
+                    } else {
+                        Tag::Thumb
+                    };
+
+                    (*value as u32, tag)
+                })
+                .collect::<Vec<_>>();
+
+            tags.sort_by_key(|tag| tag.0);
+        }
+
+        let address = if target_.is_thumb() {
+            // clear the thumb bit
+            value & !1
+        } else {
+            *value
+        };
+
+        let text = sect.raw_data(elf);
+        let (start, end) = (address as usize, (address + size) as usize);
+        if end > text.len() {
+            continue;
+        }
+
+        let (_, _, _, _, local) = crate::decode(target_, &text[start..end], address, &tags);
+        locals.push((name.clone(), local));
+    }
+
+    Ok(locals)
+}
+
+// `(name, section index, value, size, is_function)` of a symbol defined in an object file
+fn symbol<E>(elf: &ElfFile, entry: &E) -> Option<(String, u16, u64, u64, bool)>
+where
+    E: Entry,
+{
+    let name = entry.get_name(elf).ok()?;
+    let shndx = entry.shndx();
+
+    // `SHN_UNDEF`, `SHN_ABS`, etc.
+    if shndx == 0 || shndx >= 0xff00 {
+        return None;
+    }
+
+    let is_function = entry.get_type() == Ok(SymbolType::Func)
+        && matches!(entry.get_binding(), Ok(Binding::Global) | Ok(Binding::Weak));
+
+    Some((
+        name.to_owned(),
+        shndx,
+        entry.value(),
+        entry.size(),
+        is_function,
+    ))
+}
+
+/// Parses a C function signature like `float (float, float)`; `word` is the size, in bits, of
+/// pointers, `size_t` and `long`
+pub(crate) fn signature(c: &str, word: usize) -> Option<FnSig<'static>> {
+    let start = c.find('(')?;
+    let end = c.rfind(')')?;
+
+    if !c[end + 1..].trim().is_empty() {
+        return None;
+    }
+
+    let output = match c[..start].trim() {
+        "void" => None,
+        output => Some(Box::new(ty(output, word)?)),
+    };
+
+    let params = c[start + 1..end].trim();
+    let inputs = if params.is_empty() || params == "void" {
+        vec![]
+    } else {
+        params
+            .split(',')
+            .map(|param| ty(param, word))
+            .collect::<Option<Vec<_>>>()?
+    };
+
+    Some(FnSig { inputs, output })
+}
+
+// NOTE signedness and `const` are irrelevant in the LLVM-IR
+fn ty(c: &str, word: usize) -> Option<Type<'static>> {
+    let c = c.trim();
+
+    if let Some(pointee) = c.strip_suffix('*') {
+        let pointee = pointee.trim();
+        let pointee = pointee.strip_prefix("const ").unwrap_or(pointee);
+
+        // `void *` is lowered to `i8*`, like `*mut u8`
+        return Some(Type::Pointer(Box::new(if pointee == "void" {
+            Type::Integer(8)
+        } else {
+            ty(pointee, word)?
+        })));
+    }
+
+    let c = c.strip_prefix("const ").unwrap_or(c);
+    let c = c
+        .strip_prefix("unsigned ")
+        .or_else(|| c.strip_prefix("signed "))
+        .unwrap_or(c);
+
+    Some(match c {
+        "char" | "int8_t" | "uint8_t" => Type::Integer(8),
+        "short" | "int16_t" | "uint16_t" => Type::Integer(16),
+        "int" | "unsigned" | "int32_t" | "uint32_t" => Type::Integer(32),
+        "long long" | "int64_t" | "uint64_t" => Type::Integer(64),
+        "long" | "size_t" | "ssize_t" | "intptr_t" | "uintptr_t" => Type::Integer(word),
+        "float" => Type::Float,
+        "double" => Type::Double,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{FnSig, Type};
+
+    use super::{Builtin, Builtins};
+
+    #[test]
+    fn bundled() {
+        for target in &[
+            "thumbv6m-none-eabi",
+            "thumbv7m-none-eabi",
+            "thumbv7em-none-eabi",
+            "thumbv7em-none-eabihf",
+        ] {
+            let builtins = Builtins::bundled(target);
+
+            assert!(!builtins.toolchain.is_empty());
+            assert!(builtins.get("__aeabi_memcpy").is_some());
+        }
+
+        assert_eq!(
+            Builtins::bundled("x86_64-unknown-linux-gnu"),
+            Builtins::default()
+        );
+    }
+
+    #[test]
+    fn is_fresh() {
+        let builtins = Builtins::bundled("thumbv7m-none-eabi");
+
+        assert!(builtins.is_fresh(&builtins.toolchain));
+        assert!(builtins.is_fresh(&format!("{}-nightly", builtins.toolchain)));
+        assert!(builtins.is_fresh(&format!("{}-beta.2", builtins.toolchain)));
+        assert!(!builtins.is_fresh("0.1.0-nightly"));
+    }
+
+    #[test]
+    fn signatures() {
+        // no builtins file ships for ARMv8-M
+        let builtins = Builtins::bundled("thumbv8m.main-none-eabi");
+        assert!(builtins.get("__aeabi_memcpy").is_none());
+
+        let signatures = Builtins::signatures();
+        assert_eq!(
+            signatures.get("__aeabi_memcpy"),
+            Some(&Builtin {
+                name: "__aeabi_memcpy".to_owned(),
+                local: None,
+                signature: Some("void (void *, const void *, size_t)".to_owned()),
+            })
+        );
+        // uses a non-standard calling convention
+        assert_eq!(
+            signatures
+                .get("__aeabi_uldivmod")
+                .map(|builtin| &builtin.signature),
+            Some(&None)
+        );
+        assert!(signatures
+            .functions
+            .iter()
+            .all(|builtin| builtin.local.is_none()));
+    }
+
+    #[test]
+    fn parse() {
+        let builtins = Builtins::parse(
+            r#"
+toolchain = "1.33.0"
+
+[[function]]
+name = "__aeabi_fadd"
+local = 8
+signature = "float (float, float)"
+
+[[function]]
+name = "__aeabi_uldivmod"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            builtins,
+            Builtins {
+                toolchain: "1.33.0".to_owned(),
+                functions: vec![
+                    Builtin {
+                        name: "__aeabi_fadd".to_owned(),
+                        local: Some(8),
+                        signature: Some("float (float, float)".to_owned()),
+                    },
+                    Builtin {
+                        name: "__aeabi_uldivmod".to_owned(),
+                        local: None,
+                        signature: None,
+                    },
+                ],
+            }
+        );
+
+        // round trip
+        assert_eq!(
+            Builtins::parse(&builtins.to_toml().unwrap()).unwrap(),
+            builtins
+        );
+
+        assert!(Builtins::parse(
+            r#"
+toolchain = "1.33.0"
+
+[[function]]
+name = "foo"
+signature = "struct foo (void)"
+"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn signature() {
+        assert_eq!(
+            super::signature("void (void *, const void *, size_t)", 32),
+            Some(FnSig {
+                inputs: vec![
+                    Type::Pointer(Box::new(Type::Integer(8))),
+                    Type::Pointer(Box::new(Type::Integer(8))),
+                    Type::Integer(32),
+                ],
+                output: None,
+            })
+        );
+
+        assert_eq!(
+            super::signature("unsigned long long (long long, long long, long long *)", 32),
+            Some(FnSig {
+                inputs: vec![
+                    Type::Integer(64),
+                    Type::Integer(64),
+                    Type::Pointer(Box::new(Type::Integer(64))),
+                ],
+                output: Some(Box::new(Type::Integer(64))),
+            })
+        );
+
+        assert_eq!(
+            super::signature("float (void)", 64),
+            Some(FnSig {
+                inputs: vec![],
+                output: Some(Box::new(Type::Float)),
+            })
+        );
+
+        assert_eq!(super::signature("float (float", 32), None);
+        assert_eq!(super::signature("float (bool)", 32), None);
+    }
+}
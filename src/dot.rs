@@ -0,0 +1,157 @@
+use core::fmt::{self, Write as _};
+use std::io;
+
+use crate::CallGraph;
+
+// Font used in the dot graphs
+const FONT: &str = "monospace";
+
+/// Writes the call graph in the dot format
+pub fn dot<W>(cg: &CallGraph, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+
+    writeln!(stdout, "digraph {{")?;
+    writeln!(stdout, "    node [fontname={} shape=box]", FONT)?;
+    writeln!(stdout, "    edge [fontname={}]", FONT)?;
+
+    for (i, node) in g.raw_nodes().iter().enumerate() {
+        let node = &node.weight;
+
+        write!(stdout, "    {} [label=\"", i,)?;
+
+        let mut escaper = Escaper::new(&mut stdout);
+        write!(escaper, "{}", node.demangled).ok();
+        escaper.error?;
+
+        if let Some(location) = node.location {
+            let mut escaper = Escaper::new(&mut stdout);
+            write!(escaper, "\\n{}", location).ok();
+            escaper.error?;
+        }
+
+        if let Some(max) = node.max {
+            write!(stdout, "\\nmax {}", max)?;
+        }
+
+        write!(stdout, "\\nlocal = {}", node.local)?;
+
+        if let Some(origin) = node.origin {
+            let mut escaper = Escaper::new(&mut stdout);
+            write!(escaper, " ({})", origin).ok();
+            escaper.error?;
+        }
+
+        write!(stdout, "\"")?;
+
+        if node.dashed {
+            write!(stdout, " style=dashed")?;
+        }
+
+        writeln!(stdout, "]")?;
+    }
+
+    for edge in g.raw_edges() {
+        write!(
+            stdout,
+            "    {} -> {}",
+            edge.source().index(),
+            edge.target().index()
+        )?;
+
+        if let Some(location) = edge.weight.location {
+            write!(stdout, " [label=\"")?;
+
+            let mut escaper = Escaper::new(&mut stdout);
+            write!(escaper, "{}", location).ok();
+            escaper.error?;
+
+            write!(stdout, "\"]")?;
+        }
+
+        writeln!(stdout)?;
+    }
+
+    for (i, cycle) in cg.cycles.iter().enumerate() {
+        writeln!(stdout, "\n    subgraph cluster_{} {{", i)?;
+        writeln!(stdout, "        style=dashed")?;
+        writeln!(stdout, "        fontname={}", FONT)?;
+        writeln!(stdout, "        label=\"SCC{}\"", i)?;
+
+        for node in cycle {
+            writeln!(stdout, "        {}", node.index())?;
+        }
+
+        writeln!(stdout, "    }}")?;
+    }
+
+    if !cg.assumptions.is_empty() {
+        write!(
+            stdout,
+            "\n    fontname={}\n    labeljust=l\n    label=\"assumptions:",
+            FONT
+        )?;
+
+        for assumption in &cg.assumptions {
+            let mut escaper = Escaper::new(&mut stdout);
+            write!(escaper, "\\l- {}", assumption).ok();
+            escaper.error?;
+        }
+
+        writeln!(stdout, "\\l\"")?;
+    }
+
+    writeln!(stdout, "}}")
+}
+
+struct Escaper<W>
+where
+    W: io::Write,
+{
+    writer: W,
+    error: io::Result<()>,
+}
+
+impl<W> Escaper<W>
+where
+    W: io::Write,
+{
+    fn new(writer: W) -> Self {
+        Escaper {
+            writer,
+            error: Ok(()),
+        }
+    }
+}
+
+impl<W> fmt::Write for Escaper<W>
+where
+    W: io::Write,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        match (|| -> io::Result<()> {
+            if c == '"' {
+                write!(self.writer, "\\")?;
+            }
+
+            write!(self.writer, "{}", c)
+        })() {
+            Err(e) => {
+                self.error = Err(e);
+
+                Err(fmt::Error)
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
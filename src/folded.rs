@@ -0,0 +1,233 @@
+use std::{collections::HashMap, io};
+
+use petgraph::{graph::NodeIndex, Direction};
+
+use crate::{CallGraph, Diagnostic, Max, Rule};
+
+/// Writes the call stacks of the call graph in the "folded stacks" format (`root;callee;callee N`)
+/// that flame graph tools like `inferno` and `flamegraph.pl` take as input
+///
+/// Every path from the start point or, if there's no start point, from each root of the call graph
+/// (nodes that have no callers) is expanded; `N` is the local stack usage of the last frame of the
+/// stack. Cycles (SCCs) are collapsed into a single frame. At most `max_stacks` stacks are
+/// expanded; the returned warning says so if some stacks were left out
+pub fn folded<W>(cg: &CallGraph, max_stacks: usize, stdout: W) -> io::Result<Vec<Diagnostic>>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+
+    let roots = if let Some(start) = cg.start {
+        vec![start]
+    } else {
+        g.node_indices()
+            .filter(|idx| {
+                g.neighbors_directed(*idx, Direction::Incoming)
+                    .all(|caller| caller == *idx)
+            })
+            .collect()
+    };
+
+    let mut sccs = HashMap::new();
+    for (i, cycle) in cg.cycles.iter().enumerate() {
+        for member in cycle {
+            sccs.insert(*member, i);
+        }
+    }
+
+    let mut folder = Folder {
+        cg,
+        sccs,
+        stdout,
+        remaining: max_stacks,
+        truncated: false,
+        prefix: String::new(),
+        stack: vec![],
+    };
+
+    for root in roots {
+        let frame = folder.frame(root);
+        folder.walk(frame)?;
+    }
+
+    let mut diagnostics = vec![];
+    if folder.truncated {
+        warning!(
+            diagnostics,
+            Rule::Output,
+            "folded: stopped after expanding {} stacks; the output is incomplete (see \
+             `--max-stacks`)",
+            max_stacks
+        );
+    }
+
+    Ok(diagnostics)
+}
+
+// a function or a whole cycle
+#[derive(Clone, Copy, PartialEq)]
+enum Frame {
+    Node(NodeIndex),
+    // index into `CallGraph.cycles`
+    Cycle(usize),
+}
+
+struct Folder<'a, 'b, W> {
+    cg: &'b CallGraph<'a>,
+    // node -> cycle it belongs to
+    sccs: HashMap<NodeIndex, usize>,
+    stdout: W,
+    // how many more stacks we are allowed to expand
+    remaining: usize,
+    // whether we skipped a stack because we ran out of `remaining`
+    truncated: bool,
+    // the current stack, in folded form
+    prefix: String,
+    stack: Vec<Frame>,
+}
+
+impl<'a, 'b, W> Folder<'a, 'b, W>
+where
+    W: io::Write,
+{
+    fn frame(&self, idx: NodeIndex) -> Frame {
+        self.sccs
+            .get(&idx)
+            .map(|i| Frame::Cycle(*i))
+            .unwrap_or(Frame::Node(idx))
+    }
+
+    fn name(&self, frame: Frame) -> String {
+        let g = &self.cg.graph;
+
+        let name = match frame {
+            Frame::Node(idx) => g[idx].demangled.clone(),
+            Frame::Cycle(i) => format!(
+                "SCC{} ({})",
+                i,
+                self.cg.cycles[i]
+                    .iter()
+                    .map(|member| &*g[*member].demangled)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+
+        // `;` separates frames (and it appears in array types like `[u8; 4]`)
+        name.replace(';', ":")
+    }
+
+    fn local(&self, frame: Frame) -> u64 {
+        match frame {
+            Frame::Node(idx) => Into::<Max>::into(self.cg.graph[idx].local).value(),
+            Frame::Cycle(i) => self.cg.cycle_locals[i].value(),
+        }
+    }
+
+    fn callees(&self, frame: Frame) -> Vec<Frame> {
+        let members = match frame {
+            Frame::Node(idx) => vec![idx],
+            Frame::Cycle(i) => self.cg.cycles[i].clone(),
+        };
+
+        let mut callees = vec![];
+        for member in members {
+            for callee in self.cg.graph.neighbors(member) {
+                let callee = self.frame(callee);
+
+                if callee != frame && !callees.contains(&callee) {
+                    callees.push(callee);
+                }
+            }
+        }
+
+        callees
+    }
+
+    fn walk(&mut self, frame: Frame) -> io::Result<()> {
+        if self.remaining == 0 {
+            self.truncated = true;
+            return Ok(());
+        }
+        self.remaining -= 1;
+
+        let len = self.prefix.len();
+        if !self.stack.is_empty() {
+            self.prefix.push(';');
+        }
+        let name = self.name(frame);
+        self.prefix.push_str(&name);
+
+        // frames that use no stack would have zero width in the flame graph
+        let local = self.local(frame);
+        if local != 0 {
+            writeln!(self.stdout, "{} {}", self.prefix, local)?;
+        }
+
+        self.stack.push(frame);
+        for callee in self.callees(frame) {
+            // collapsing the SCCs leaves no cycles but be defensive
+            if !self.stack.contains(&callee) {
+                self.walk(callee)?;
+            }
+        }
+        self.stack.pop();
+        self.prefix.truncate(len);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+
+    use crate::{CallGraph, Edge, Max, Node};
+
+    #[test]
+    fn folded() {
+        // main -> foo -> (bar <-> baz) -> [u8; 4]::clone; main -> quux
+        let mut g = DiGraph::new();
+        let main = g.add_node(Node("main", Some(8), false));
+        let foo = g.add_node(Node("foo", Some(0), false));
+        let bar = g.add_node(Node("bar", Some(16), false));
+        let baz = g.add_node(Node("baz", Some(4), false));
+        let clone = g.add_node(Node("[u8; 4]::clone", Some(2), false));
+        let quux = g.add_node(Node("quux", None, false));
+        g.add_edge(main, foo, Edge::default());
+        g.add_edge(foo, bar, Edge::default());
+        g.add_edge(bar, baz, Edge::default());
+        g.add_edge(baz, bar, Edge::default());
+        g.add_edge(baz, clone, Edge::default());
+        g.add_edge(main, quux, Edge::default());
+
+        let cg = CallGraph {
+            graph: g,
+            cycles: vec![vec![bar, baz]],
+            cycle_locals: vec![Max::LowerBound(20)],
+            start: None,
+            vectors: vec![],
+            diagnostics: vec![],
+            assumptions: vec![],
+        };
+
+        let mut out = vec![];
+        assert!(super::folded(&cg, 100, &mut out).unwrap().is_empty());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main 8
+main;foo;SCC0 (bar, baz) 20
+main;foo;SCC0 (bar, baz);[u8: 4]::clone 2
+"
+        );
+
+        // main, quux, foo
+        let mut out = vec![];
+        assert_eq!(super::folded(&cg, 3, &mut out).unwrap().len(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "main 8\n");
+
+        // main, foo, SCC0, [u8; 4]::clone, quux; all of them fit
+        let mut out = vec![];
+        assert!(super::folded(&cg, 5, &mut out).unwrap().is_empty());
+    }
+}
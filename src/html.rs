@@ -0,0 +1,45 @@
+use std::io;
+
+use crate::{json, CallGraph};
+
+// page with a `{{data}}` placeholder where the JSON document goes
+const TEMPLATE: &str = include_str!("html/explorer.html");
+
+/// Writes the call graph as a single HTML page to explore it in a web browser
+///
+/// The page embeds the call graph, in the JSON format (see `json`), and the script that renders it
+/// so it doesn't need network access
+pub fn html<W>(cg: &CallGraph, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let json = serde_json::to_string(&json::CallGraph::from(cg))?;
+
+    let (head, tail) = TEMPLATE
+        .split_once("{{data}}")
+        .expect("BUG: no placeholder in the HTML template");
+    stdout.write_all(head.as_bytes())?;
+    stdout.write_all(embed(&json).as_bytes())?;
+    stdout.write_all(tail.as_bytes())
+}
+
+// escapes the sequences that would end the `<script>` element that contains the JSON document
+fn embed(json: &str) -> String {
+    json.replace("</", "<\\/").replace("<!--", "<\\u0021--")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn embed() {
+        let json = r#"{"name":"</script><!--"}"#;
+        let embedded = super::embed(json);
+
+        assert!(!embedded.contains("</"));
+        assert!(!embedded.contains("<!--"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&embedded).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+}
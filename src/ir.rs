@@ -5,11 +5,13 @@ use nom::{types::CompleteStr, *};
 
 mod define;
 mod item;
+mod metadata;
 mod ty;
 
 pub use crate::ir::{
     define::{Define, Stmt},
-    item::{Declare, Item},
+    item::{Declare, GlobalVariable, Item},
+    metadata::{DebugInfo, DebugInfos, Location, Metadata},
     ty::{type_, Type},
 };
 
@@ -51,6 +53,18 @@ pub fn parse<'a>(ll: &'a str) -> Result<Vec<Item<'a>>, failure::Error> {
     })
 }
 
+/// The target triple the LLVM-IR module was compiled for (`target triple = "thumbv7m-none-eabi"`)
+///
+/// NOTE this is LLVM's triple, which for some targets is not the name `rustc` uses (e.g. `riscv32`
+/// instead of `riscv32imac-unknown-none-elf`)
+pub fn target_triple(ll: &str) -> Option<&str> {
+    ll.lines().find_map(|line| {
+        line.strip_prefix("target triple = \"")?
+            .trim_end()
+            .strip_suffix('"')
+    })
+}
+
 named!(items<CompleteStr, Vec<Item>>, do_parse!(
     items: separated_list_complete!(many1!(line_ending), crate::ir::item::item) >>
         many0!(line_ending) >>
@@ -58,6 +72,36 @@ named!(items<CompleteStr, Vec<Item>>, do_parse!(
         (items)
 ));
 
+// finds the `!dbg !N` attachment in what's left of a line
+fn dbg(rest: &str) -> Option<u32> {
+    const DBG: &str = "!dbg !";
+
+    let start = rest.find(DBG)? + DBG.len();
+    rest[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|id| id.parse().ok())
+}
+
+// symbols (`@name`) mentioned in `s`, in order of appearance
+fn refs(s: &str) -> Vec<&str> {
+    let mut refs = vec![];
+
+    let mut rest = s;
+    while let Some(pos) = rest.find('@') {
+        match symbol(CompleteStr(&rest[pos..])) {
+            Ok((after, name)) => {
+                refs.push(name);
+                rest = after.0;
+            }
+
+            Err(_) => rest = &rest[pos + 1..],
+        }
+    }
+
+    refs
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Comment;
 
@@ -76,7 +120,7 @@ fn ident<'a>(input: CompleteStr<'a>) -> IResult<CompleteStr<'a>, Ident<'a>> {
     map_res!(
         input,
         take_while1!(|c: char| c.is_alphanumeric() || "-$._".contains(c)),
-        |s: CompleteStr<'a>| if s.chars().next().unwrap_or('\0').is_digit(10) {
+        |s: CompleteStr<'a>| if s.chars().next().unwrap_or('\0').is_ascii_digit() {
             Err(())
         } else {
             Ok(Ident(s.0))
@@ -93,6 +137,16 @@ named!(global<CompleteStr, Global>, do_parse!(
         (Global(s)))
 );
 
+// like `global` but keeps the names of unnamed globals (`@0` -> `0`)
+named!(
+    symbol<CompleteStr<'_>, &str>,
+    do_parse!(
+        char!('@') >>
+            s: alt!(map!(string, |s| s.0) | map!(digit, |d| d.0) | map!(ident, |i| i.0)) >>
+            (s)
+    )
+);
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Local;
 
@@ -159,11 +213,9 @@ named!(getelementptr<CompleteStr, GetElementPtr>, do_parse!(
         (GetElementPtr)
 ));
 
-named!(name<CompleteStr, &str>,
-       alt!(
-           map!(string, |s| s.0) |
-           map!(ident, |i| i.0)
-       )
+named!(
+    name<CompleteStr<'_>, &str>,
+    alt!(map!(string, |s| s.0) | map!(ident, |i| i.0))
 );
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -300,6 +352,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn refs() {
+        assert_eq!(
+            super::refs(
+                r#"<{ i8*, [8 x i8], i8* }> <{ i8* bitcast (void (%Foo*)* @"_ZN4core3ptr13drop_in_place17h0E" to i8*), [8 x i8] c"\04\00\00\00\04\00\00\00", i8* bitcast (i1 (%Foo*)* @_ZN3app3foo17h1E to i8*) }>, align 4"#
+            ),
+            vec!["_ZN4core3ptr13drop_in_place17h0E", "_ZN3app3foo17h1E"]
+        );
+
+        assert_eq!(
+            super::refs("  store {}* bitcast (<{ i8*, [8 x i8], i8* }>* @vtable.0 to {}*), {}** %2, align 4, !dbg !30"),
+            vec!["vtable.0"]
+        );
+
+        assert_eq!(super::refs("  %3 = load i32, i32* @0, align 4"), vec!["0"]);
+        assert_eq!(super::refs("  ret void"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn target_triple() {
+        assert_eq!(
+            super::target_triple(
+                r#"; ModuleID = 'app.4kkmqexx-cgu.0'
+source_filename = "app.4kkmqexx-cgu.0"
+target datalayout = "e-m:e-p:32:32-i64:64-v128:64:128-a:0:32-n32-S64"
+target triple = "thumbv7m-none-eabi"
+"#
+            ),
+            Some("thumbv7m-none-eabi")
+        );
+
+        assert_eq!(super::target_triple("; ModuleID = 'app'\n"), None);
+    }
+
     #[test]
     fn string() {
         assert_eq!(
@@ -7,6 +7,16 @@ pub struct Define<'a> {
     pub name: &'a str,
     pub sig: FnSig<'a>,
     pub stmts: Vec<Stmt<'a>>,
+    // `DISubprogram` metadata (`!dbg !N`)
+    pub dbg: Option<u32>,
+    // symbols the body refers to, other than the callees of direct calls (e.g. vtables and `fn`
+    // pointers)
+    pub refs: Vec<&'a str>,
+    // subset of `refs` that the body writes to memory; any other function could read these back
+    pub stores: Vec<&'a str>,
+    // subset of `refs` that the body passes as arguments to other functions; the callee could
+    // write these to memory (e.g. a `register(f)` function that fills a table of handlers)
+    pub args: Vec<&'a str>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -14,11 +24,12 @@ pub enum Stmt<'a> {
     // `  call void asm sideeffect "cpsid i"`
     Asm(&'a str),
 
-    BitcastCall(Option<&'a str>),
+    // the second field is the `DILocation` metadata of the call site (`!dbg !N`)
+    BitcastCall(Option<&'a str>, Option<u32>),
 
-    DirectCall(&'a str),
+    DirectCall(&'a str, Option<u32>),
 
-    IndirectCall(FnSig<'a>),
+    IndirectCall(FnSig<'a>, Option<u32>),
 
     Comment,
 
@@ -49,13 +60,87 @@ named!(pub parse<CompleteStr, Define>, do_parse!(
             do_parse!(char!(',') >> space >> (())),
             map!(parameter, |p| p.0)
         ) >> char!(')') >>
-    // TODO we likely want to parse the metadata (`!dbg !0`) that comes after the parameter list
-        not_line_ending >> line_ending >>
-        stmts: separated_nonempty_list!(many1!(line_ending), call!(super::define::stmt)) >>
+        rest: not_line_ending >> line_ending >>
+        stmts: separated_nonempty_list!(many1!(line_ending), stmt_refs) >>
         opt!(line_ending) >> tag!("}") >>
-        (Define { name: name.0, stmts, sig: FnSig { inputs, output: output.map(Box::new) } })
+        ({
+            let mut define = Define {
+                name: name.0,
+                stmts: vec![],
+                sig: FnSig { inputs, output: output.map(Box::new) },
+                dbg: super::dbg(rest.0),
+                refs: vec![],
+                stores: vec![],
+                args: vec![],
+            };
+
+            for (stmt, refs, use_) in stmts {
+                define.stmts.push(stmt);
+
+                match use_ {
+                    Use::Store => define.stores.extend(&refs),
+                    Use::Call => define.args.extend(&refs),
+                    Use::Other => {}
+                }
+                define.refs.extend(refs);
+            }
+
+            define
+        })
 ));
 
+// how a statement uses the symbols it refers to
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Use {
+    // as arguments of a function call
+    Call,
+    // as values written to memory (or as the address they are written to)
+    Store,
+    Other,
+}
+
+// a statement, the symbols it refers to (other than the callee) and how it uses them
+fn stmt_refs<'a>(
+    input: CompleteStr<'a>,
+) -> IResult<CompleteStr<'a>, (Stmt<'a>, Vec<&'a str>, Use)> {
+    let (rest, stmt) = stmt(input)?;
+
+    let line = &input.0[..input.0.len() - rest.0.len()];
+    let mut refs = super::refs(line);
+    match stmt {
+        Stmt::BitcastCall(Some(callee), _) | Stmt::DirectCall(callee, _) => {
+            if let Some(pos) = refs.iter().position(|sym| *sym == callee) {
+                refs.remove(pos);
+            }
+        }
+
+        _ => {}
+    }
+
+    // NOTE we discard the LHS of assignments
+    let op = line.trim_start();
+    let op = match op.split_once(" = ") {
+        Some((_, rhs)) if op.starts_with('%') => rhs,
+        _ => op,
+    };
+    let use_ = match stmt {
+        Stmt::Asm(_) | Stmt::BitcastCall(..) | Stmt::DirectCall(..) | Stmt::IndirectCall(..) => {
+            Use::Call
+        }
+        // `invoke`s of callees that we don't parse
+        _ if op.starts_with("invoke ") => Use::Call,
+        _ if op.starts_with("store ")
+            || op.starts_with("atomicrmw ")
+            || op.starts_with("cmpxchg ") =>
+        {
+            Use::Store
+        }
+        _ => Use::Other,
+    };
+
+    Ok((rest, (stmt, refs, use_)))
+}
+
 named!(label<CompleteStr, Stmt>, do_parse!(
     alt!(map!(super::ident, drop) | map!(super::string, drop)) >>
         char!(':') >>
@@ -103,8 +188,8 @@ named!(bitcast_call<CompleteStr, Stmt>, do_parse!(
         alt!(map!(call!(super::type_), drop) | map!(tag!("void"), drop)) >> space >>
         name: call!(super::bitcast) >>
     // NOTE shortcut
-        not_line_ending >>
-        (Stmt::BitcastCall(name.0))
+        rest: not_line_ending >>
+        (Stmt::BitcastCall(name.0, super::dbg(rest.0)))
 ));
 
 named!(direct_call<CompleteStr, Stmt>, do_parse!(
@@ -113,10 +198,9 @@ named!(direct_call<CompleteStr, Stmt>, do_parse!(
         many0!(do_parse!(call!(super::attribute) >> space >> (()))) >>
         alt!(map!(call!(super::type_), drop) | map!(tag!("void"), drop)) >> space >>
         name: call!(super::function) >>
-    // TODO we likely want to parse the metadata (`!dbg !0`) that comes after the argument list
     // NOTE shortcut
-        char!('(') >> not_line_ending >>
-        (Stmt::DirectCall(name.0))
+        char!('(') >> rest: not_line_ending >>
+        (Stmt::DirectCall(name.0, super::dbg(rest.0)))
 ));
 
 named!(indirect_call<CompleteStr, Stmt>, do_parse!(
@@ -133,10 +217,9 @@ named!(indirect_call<CompleteStr, Stmt>, do_parse!(
             ),
             char!(')')
         ) >>
-    // TODO we likely want to parse the metadata (`!dbg !0`) that comes after the argument list
     // NOTE shortcut
-        not_line_ending >>
-        (Stmt::IndirectCall(FnSig { inputs, output: output.map(Box::new) }))
+        rest: not_line_ending >>
+        (Stmt::IndirectCall(FnSig { inputs, output: output.map(Box::new) }, super::dbg(rest.0)))
 ));
 
 named!(other<CompleteStr, Stmt>, do_parse!(
@@ -235,7 +318,7 @@ mod tests {
     fn assign() {
         assert_eq!(
             super::assign(S(r#"%0 = tail call nonnull i32 (i32)* @foo(), !dbg !1200"#)),
-            Ok((S(""), Stmt::DirectCall("foo")))
+            Ok((S(""), Stmt::DirectCall("foo", Some(1200))))
         );
 
         assert_eq!(
@@ -247,12 +330,12 @@ mod tests {
                     Type::Integer(32),
                 ],
                 output: Some(Box::new(Type::Integer(1))),
-            })))
+            }, Some(30714))))
         );
 
         assert_eq!(
             super::assign(S(r#"%_0.sroa.0.0.insert.insert.i.i39 = tail call i32 @llvm.bswap.i32(i32 %page.0.i38) #9"#)),
-            Ok((S(""), Stmt::DirectCall("llvm.bswap.i32")))
+            Ok((S(""), Stmt::DirectCall("llvm.bswap.i32", None)))
         );
     }
 
@@ -262,7 +345,7 @@ mod tests {
             super::bitcast_call(S(
                 r#"tail call i32 bitcast (i8* @__sbss to i32 ()*)() #6, !dbg !1177"#
             )),
-            Ok((S(""), Stmt::BitcastCall(Some("__sbss"))))
+            Ok((S(""), Stmt::BitcastCall(Some("__sbss"), Some(1177))))
         );
     }
 
@@ -272,17 +355,17 @@ mod tests {
             super::direct_call(S(
                 r#"call void @llvm.dbg.value(metadata %"blue_pill::ItmLogger"* %0, metadata !2111, metadata !DIExpression()), !dbg !2115"#
             )),
-            Ok((S(""), Stmt::DirectCall("llvm.dbg.value")))
+            Ok((S(""), Stmt::DirectCall("llvm.dbg.value", Some(2115))))
         );
 
         assert_eq!(
             super::direct_call(S(r#"tail call nonnull i32 (i32)* @foo(), !dbg !1200"#)),
-            Ok((S(""), Stmt::DirectCall("foo")))
+            Ok((S(""), Stmt::DirectCall("foo", Some(1200))))
         );
 
         assert_eq!(
             super::direct_call(S(r#"tail call i32 @llvm.bswap.i32(i32 %page.0.i) #9"#)),
-            Ok((S(""), Stmt::DirectCall("llvm.bswap.i32")))
+            Ok((S(""), Stmt::DirectCall("llvm.bswap.i32", None)))
         );
     }
 
@@ -292,10 +375,13 @@ mod tests {
             super::indirect_call(S(r#"tail call i32 %0(i32 0) #8, !dbg !1200"#)),
             Ok((
                 S(""),
-                Stmt::IndirectCall(FnSig {
-                    inputs: vec![Type::Integer(32)],
-                    output: Some(Box::new(Type::Integer(32)))
-                })
+                Stmt::IndirectCall(
+                    FnSig {
+                        inputs: vec![Type::Integer(32)],
+                        output: Some(Box::new(Type::Integer(32)))
+                    },
+                    Some(1200)
+                )
             ))
         );
 
@@ -310,7 +396,7 @@ mod tests {
                         Type::Integer(64),
                     ],
                     output: Some(Box::new(Type::Integer(1)))
-                })
+                }, Some(4725))
             ))
         );
 
@@ -325,7 +411,7 @@ mod tests {
                         Type::Integer(32),
                     ],
                     output: Some(Box::new(Type::Integer(1)))
-                })
+                }, Some(5301))
             ))
         );
     }
@@ -393,7 +479,11 @@ start:
                 (S(""),
                  Define {
                      name: "_ZN4core3ptr18real_drop_in_place17h10d0d6d6b26fb8afE",
+                     dbg: Some(2105),
                      stmts: vec![Stmt::Label, Stmt::Other],
+                     refs: vec![],
+                     stores: vec![],
+                     args: vec![],
                      sig: FnSig {
                          inputs: vec![Type::Pointer(Box::new(Type::Alias("blue_pill::ItmLogger")))],
                          output: None,
@@ -412,7 +502,11 @@ start:
                 (S(""),
                  Define {
                      name: "_ZN3std10sys_common12thread_local22register_dtor_fallback17h254497a6d25774eeE",
+                     dbg: Some(5158),
                      stmts: vec![Stmt::Label, Stmt::Other],
+                     refs: vec![],
+                     stores: vec![],
+                     args: vec![],
                      sig: FnSig {
                          inputs: vec![
                              Type::Pointer(Box::new(Type::Integer(8))),
@@ -437,7 +531,11 @@ start:
                 (S(""),
                  Define {
                      name: "_ZN3std9panicking20rust_panic_with_hook17hac9cf78024704ab4E",
+                     dbg: Some(6634),
                      stmts: vec![Stmt::Label, Stmt::Other],
+                     refs: vec![],
+                     stores: vec![],
+                     args: vec![],
                      sig: FnSig {
                          inputs: vec![
                              Type::Pointer(Box::new(Type::Struct(vec![]))),
@@ -472,7 +570,11 @@ start:
                 S(""),
                 Define {
                     name: "foo",
+                    dbg: Some(1272),
                     stmts: vec![Stmt::Label, Stmt::Other],
+                    refs: vec![],
+                    stores: vec![],
+                    args: vec![],
                     sig: FnSig {
                         inputs: vec![],
                         output: Some(Box::new(Type::Pointer(Box::new(Type::Pointer(Box::new(
@@ -499,12 +601,16 @@ start:
                 S(""),
                 Define {
                     name: "_ZN3app3foo17h3337355bfdc88d96E",
+                    dbg: Some(1183),
                     stmts: vec![
                         Stmt::Label,
-                        Stmt::DirectCall("llvm.dbg.value"),
+                        Stmt::DirectCall("llvm.dbg.value", Some(1188)),
                         Stmt::Other,
                         Stmt::Other,
                     ],
+                    refs: vec![],
+                    stores: vec![],
+                    args: vec![],
                     sig: FnSig {
                         inputs: vec![Type::Float],
                         output: Some(Box::new(Type::Float)),
@@ -512,5 +618,90 @@ start:
                 }
             ))
         );
+
+        assert_eq!(
+            super::parse(S(r#"define void @main() unnamed_addr #0 !dbg !1200 {
+start:
+  tail call fastcc void @_ZN3app3use17h0E({}* nonnull align 1 bitcast (<{}>* @1 to {}*), [3 x i32]* noalias readonly align 4 dereferenceable(12) bitcast (<{ i8*, [8 x i8], i8* }>* @vtable.0 to [3 x i32]*)), !dbg !1201
+  store [3 x i32]* bitcast (<{ i8*, [8 x i8], i8* }>* @vtable.1 to [3 x i32]*), [3 x i32]** getelementptr inbounds (<{ [8 x i8] }>, <{ [8 x i8] }>* @_ZN3app2TO17h2E, i32 0, i32 0, i32 4), align 4, !dbg !1202
+  ret void, !dbg !1203
+}"#)),
+            Ok((
+                S(""),
+                Define {
+                    name: "main",
+                    dbg: Some(1200),
+                    stmts: vec![
+                        Stmt::Label,
+                        Stmt::DirectCall("_ZN3app3use17h0E", Some(1201)),
+                        Stmt::Other,
+                        Stmt::Other,
+                    ],
+                    refs: vec!["1", "vtable.0", "vtable.1", "_ZN3app2TO17h2E"],
+                    stores: vec!["vtable.1", "_ZN3app2TO17h2E"],
+                    args: vec!["1", "vtable.0"],
+                    sig: FnSig {
+                        inputs: vec![],
+                        output: None,
+                    },
+                }
+            ))
+        );
+
+        // `bar`'s address is written to memory by `register`, not by `init`
+        assert_eq!(
+            super::parse(S(r#"define void @init() unnamed_addr #0 !dbg !1300 {
+start:
+  tail call fastcc void @_ZN3app8register17h1E(void ()* nonnull @_ZN3app3bar17h2E), !dbg !1301
+  ret void, !dbg !1302
+}"#)),
+            Ok((
+                S(""),
+                Define {
+                    name: "init",
+                    dbg: Some(1300),
+                    stmts: vec![
+                        Stmt::Label,
+                        Stmt::DirectCall("_ZN3app8register17h1E", Some(1301)),
+                        Stmt::Other,
+                    ],
+                    refs: vec!["_ZN3app3bar17h2E"],
+                    stores: vec![],
+                    args: vec!["_ZN3app3bar17h2E"],
+                    sig: FnSig {
+                        inputs: vec![],
+                        output: None,
+                    },
+                }
+            ))
+        );
+
+        // `log::set_logger(&LOGGER)` stores the trait object (and its vtable) in a `static`
+        assert_eq!(
+            super::parse(S(r#"define void @init() unnamed_addr #0 !dbg !1400 {
+start:
+  %0 = tail call zeroext i1 @_ZN3log10set_logger17h3E({}* nonnull align 1 bitcast (<{}>* @_ZN3app6LOGGER17h4E to {}*), [6 x i32]* noalias readonly align 4 dereferenceable(24) bitcast (<{ i8*, [8 x i8], i8*, i8*, i8* }>* @vtable.2 to [6 x i32]*)), !dbg !1401
+  ret void, !dbg !1402
+}"#)),
+            Ok((
+                S(""),
+                Define {
+                    name: "init",
+                    dbg: Some(1400),
+                    stmts: vec![
+                        Stmt::Label,
+                        Stmt::DirectCall("_ZN3log10set_logger17h3E", Some(1401)),
+                        Stmt::Other,
+                    ],
+                    refs: vec!["_ZN3app6LOGGER17h4E", "vtable.2"],
+                    stores: vec![],
+                    args: vec!["_ZN3app6LOGGER17h4E", "vtable.2"],
+                    sig: FnSig {
+                        inputs: vec![],
+                        output: None,
+                    },
+                }
+            ))
+        );
     }
 }
@@ -1,6 +1,6 @@
 use nom::{types::CompleteStr, *};
 
-use crate::ir::{define::Define, FnSig};
+use crate::ir::{define::Define, metadata::Metadata, FnSig};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Item<'a> {
@@ -18,7 +18,7 @@ pub enum Item<'a> {
 
     // `@0 = private constant <{ [0 x i8 ]}> zeroinitializer, align 4, !dbg 0`
     // `@__sbss = external global i32`
-    Global,
+    Global(GlobalVariable<'a>),
 
     // `%Struct = type { i8, i16 }` ("new type")
     Type,
@@ -33,7 +33,8 @@ pub enum Item<'a> {
     Attributes,
 
     // `!0 = !DIGlobalVariableExpression(var: !1, expr: !DIExpression())`
-    Metadata,
+    // `None` if this is not debug information we care about
+    Metadata(Option<Metadata<'a>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -42,6 +43,16 @@ pub struct Declare<'a> {
     pub sig: Option<FnSig<'a>>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobalVariable<'a> {
+    // unnamed globals use their number here (`@0` -> `0`)
+    pub name: &'a str,
+    // symbols (functions and other globals) the initializer refers to; e.g. the methods of a vtable
+    pub refs: Vec<&'a str>,
+    // `global` rather than `constant`; the program can write to it at runtime
+    pub mutable: bool,
+}
+
 named!(comment<CompleteStr, Item>, map!(super::comment, |_| Item::Comment));
 
 named!(source_filename<CompleteStr, Item>, do_parse!(
@@ -70,12 +81,16 @@ named!(alias<CompleteStr, Item>, do_parse!(
 ));
 
 named!(global<CompleteStr, Item>, do_parse!(
-    call!(super::global) >> space >>
+    name: call!(super::symbol) >> space >>
         char!('=') >> space >>
         many0!(do_parse!(call!(super::attribute) >> space >> (()))) >>
-        alt!(tag!("global") | tag!("constant")) >> space >>
-        not_line_ending >>
-        (Item::Global)
+        kind: alt!(tag!("global") | tag!("constant")) >> space >>
+        init: not_line_ending >>
+        (Item::Global(GlobalVariable {
+            name,
+            refs: super::refs(init.0),
+            mutable: kind.0 == "global",
+        }))
 ));
 
 named!(type_<CompleteStr, Item>, do_parse!(
@@ -149,10 +164,8 @@ named!(attributes<CompleteStr, Item>, do_parse!(
 ));
 
 named!(metadata<CompleteStr, Item>, do_parse!(
-    tag!("!") >>
-    // NOTE shortcut
-        not_line_ending >>
-        (Item::Metadata)
+    line: recognize!(do_parse!(tag!("!") >> not_line_ending >> (()))) >>
+        (Item::Metadata(super::metadata::parse(line.0)))
 ));
 
 named!(pub item<CompleteStr, Item>, alt!(
@@ -172,7 +185,7 @@ named!(pub item<CompleteStr, Item>, alt!(
 mod tests {
     use nom::types::CompleteStr as S;
 
-    use crate::ir::{Declare, FnSig, Item, Type};
+    use crate::ir::{Declare, FnSig, GlobalVariable, Item, Type};
 
     #[test]
     fn alias() {
@@ -207,14 +220,59 @@ mod tests {
             super::global(S(
                 "@0 = private constant <{ [0 x i8] }> zeroinitializer, align 4, !dbg !0"
             )),
-            Ok((S(""), Item::Global))
+            Ok((
+                S(""),
+                Item::Global(GlobalVariable {
+                    name: "0",
+                    refs: vec![],
+                    mutable: false,
+                })
+            ))
         );
 
         assert_eq!(
             super::global(S(
                 "@DEVICE_PERIPHERALS = local_unnamed_addr global <{ [1 x i8] }> zeroinitializer, align 1, !dbg !175"
             )),
-            Ok((S(""), Item::Global))
+            Ok((
+                S(""),
+                Item::Global(GlobalVariable {
+                    name: "DEVICE_PERIPHERALS",
+                    refs: vec![],
+                    mutable: true,
+                })
+            ))
+        );
+
+        assert_eq!(
+            super::global(S(
+                r#"@vtable.0 = private unnamed_addr constant <{ i8*, [8 x i8], i8* }> <{ i8* bitcast (void (%Foo*)* @_ZN4core3ptr18real_drop_in_place17h0E to i8*), [8 x i8] c"\00\00\00\00\01\00\00\00", i8* bitcast (i1 (%Foo*)* @"_ZN40_$LT$app..Foo$u20$as$u20$app..Bar$GT$3bar17h1E" to i8*) }>, align 4"#
+            )),
+            Ok((
+                S(""),
+                Item::Global(GlobalVariable {
+                    name: "vtable.0",
+                    refs: vec![
+                        "_ZN4core3ptr18real_drop_in_place17h0E",
+                        "_ZN40_$LT$app..Foo$u20$as$u20$app..Bar$GT$3bar17h1E"
+                    ],
+                    mutable: false,
+                })
+            ))
+        );
+
+        assert_eq!(
+            super::global(S(
+                "@_ZN3app8HANDLERS17h0E = internal global <{ void ()*, void ()* }> <{ void ()* @_ZN3app7default17h1E, void ()* @_ZN3app7default17h1E }>, align 4, !dbg !180"
+            )),
+            Ok((
+                S(""),
+                Item::Global(GlobalVariable {
+                    name: "_ZN3app8HANDLERS17h0E",
+                    refs: vec!["_ZN3app7default17h1E", "_ZN3app7default17h1E"],
+                    mutable: true,
+                })
+            ))
         );
     }
 
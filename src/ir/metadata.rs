@@ -0,0 +1,393 @@
+use core::fmt;
+use std::{borrow::Cow, collections::HashMap, path::Path};
+
+// `!12 = distinct !DISubprogram(name: "main", scope: !3, file: !3, line: 10, ..)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata<'a> {
+    pub id: u32,
+    pub node: DebugInfo<'a>,
+}
+
+// the debug information records we care about
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugInfo<'a> {
+    // `!DIFile(filename: "src/main.rs", directory: "/home/japaric/app")`
+    File {
+        filename: &'a str,
+        directory: Option<&'a str>,
+    },
+
+    // `!DISubprogram(name: "main", scope: !3, file: !3, line: 10, ..)`
+    Subprogram {
+        file: Option<u32>,
+        line: Option<u32>,
+    },
+
+    // `!DILexicalBlock(scope: !12, file: !3, line: 11, column: 5)`
+    // `!DILexicalBlockFile(scope: !12, file: !3, discriminator: 0)`
+    LexicalBlock {
+        scope: u32,
+        file: Option<u32>,
+    },
+
+    // `!DILocation(line: 14, column: 5, scope: !12, inlinedAt: !20)`
+    Location {
+        line: u32,
+        column: Option<u32>,
+        scope: u32,
+        inlined_at: Option<u32>,
+    },
+}
+
+/// A location in the source code
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Location<'a> {
+    /// As written in the debug information; it may be relative to `directory`
+    pub file: &'a str,
+    /// The compilation directory
+    pub directory: Option<&'a str>,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+impl<'a> Location<'a> {
+    /// The path to the file; relative `file`s are joined to the `directory`
+    pub fn path(&self) -> Cow<'a, str> {
+        match self.directory {
+            Some(directory) if !directory.is_empty() && !Path::new(self.file).is_absolute() => {
+                Cow::Owned(Path::new(directory).join(self.file).display().to_string())
+            }
+
+            _ => Cow::Borrowed(self.file),
+        }
+    }
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path(), self.line)?;
+
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Debug information indexed by metadata id
+#[derive(Debug, Default)]
+pub struct DebugInfos<'a> {
+    nodes: HashMap<u32, DebugInfo<'a>>,
+}
+
+impl<'a> DebugInfos<'a> {
+    pub fn insert(&mut self, metadata: Metadata<'a>) {
+        self.nodes.insert(metadata.id, metadata.node);
+    }
+
+    /// Where the function described by the `DISubprogram` `id` is defined
+    pub fn subprogram(&self, id: u32) -> Option<Location<'a>> {
+        match self.nodes.get(&id)? {
+            DebugInfo::Subprogram {
+                file: Some(file),
+                line: Some(line),
+            } => {
+                let (file, directory) = self.file(*file)?;
+
+                Some(Location {
+                    file,
+                    directory,
+                    line: *line,
+                    column: None,
+                })
+            }
+
+            _ => None,
+        }
+    }
+
+    /// The location of a call site in the *caller*, given the `DILocation` `id` of the call
+    ///
+    /// If the call was inlined into the caller this returns the location of the outermost call
+    /// site
+    pub fn call_site(&self, mut id: u32) -> Option<Location<'a>> {
+        // `inlinedAt` chains are never cyclic but let's not loop forever on malformed input
+        for _ in 0..self.nodes.len() {
+            match self.nodes.get(&id)? {
+                DebugInfo::Location {
+                    inlined_at: Some(inlined_at),
+                    ..
+                } => id = *inlined_at,
+
+                DebugInfo::Location {
+                    line,
+                    column,
+                    scope,
+                    inlined_at: None,
+                } => {
+                    let (file, directory) = self.scope_file(*scope)?;
+
+                    return Some(Location {
+                        file,
+                        directory,
+                        line: *line,
+                        column: *column,
+                    });
+                }
+
+                _ => return None,
+            }
+        }
+
+        None
+    }
+
+    // `(filename, directory)`
+    fn file(&self, id: u32) -> Option<(&'a str, Option<&'a str>)> {
+        match self.nodes.get(&id)? {
+            DebugInfo::File {
+                filename,
+                directory,
+            } => Some((filename, *directory)),
+            _ => None,
+        }
+    }
+
+    // the file a scope (subprogram or lexical block) belongs to
+    fn scope_file(&self, mut id: u32) -> Option<(&'a str, Option<&'a str>)> {
+        for _ in 0..self.nodes.len() {
+            match self.nodes.get(&id)? {
+                DebugInfo::Subprogram {
+                    file: Some(file), ..
+                }
+                | DebugInfo::LexicalBlock {
+                    file: Some(file), ..
+                } => return self.file(*file),
+
+                DebugInfo::LexicalBlock { scope, file: None } => id = *scope,
+
+                _ => return None,
+            }
+        }
+
+        None
+    }
+}
+
+// NOTE this is not a `nom` parser; metadata we don't understand is simply ignored
+pub fn parse(line: &str) -> Option<Metadata<'_>> {
+    // `!12 = distinct !DISubprogram(..)`
+    let line = line.strip_prefix('!')?;
+    let pos = line.find(" = ")?;
+    let id = line[..pos].parse().ok()?;
+    let rhs = &line[pos + 3..];
+    let rhs = rhs.strip_prefix("distinct ").unwrap_or(rhs);
+
+    let rhs = rhs.strip_prefix("!DI")?;
+    let (start, end) = (rhs.find('(')?, rhs.rfind(')')?);
+    let kind = &rhs[..start];
+    let fields = fields(&rhs[start + 1..end]);
+
+    let field = |name| {
+        fields
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+    };
+    let number = |name| field(name).and_then(|value| value.parse().ok());
+    let reference = |name| {
+        field(name)
+            .and_then(|value| value.strip_prefix('!'))
+            .and_then(|id| id.parse().ok())
+    };
+
+    let string = |name| {
+        field(name)
+            .and_then(|value| value.strip_prefix('"'))
+            .and_then(|value| value.strip_suffix('"'))
+    };
+
+    let node = match kind {
+        "File" => DebugInfo::File {
+            filename: string("filename")?,
+            directory: string("directory"),
+        },
+
+        "Subprogram" => DebugInfo::Subprogram {
+            file: reference("file"),
+            line: number("line"),
+        },
+
+        "LexicalBlock" | "LexicalBlockFile" => DebugInfo::LexicalBlock {
+            scope: reference("scope")?,
+            file: reference("file"),
+        },
+
+        "Location" => DebugInfo::Location {
+            line: number("line")?,
+            column: number("column"),
+            scope: reference("scope")?,
+            inlined_at: reference("inlinedAt"),
+        },
+
+        _ => return None,
+    };
+
+    Some(Metadata { id, node })
+}
+
+// splits `name: "main", scope: !3, flags: DIFlagA | DIFlagB` into `(key, value)` pairs
+fn fields(s: &str) -> Vec<(&str, &str)> {
+    let mut fields = vec![];
+    let mut in_string = false;
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '{' if !in_string => depth += 1,
+            ')' | '}' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                fields.extend(key_value(&s[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.extend(key_value(&s[start..]));
+
+    fields
+}
+
+fn key_value(field: &str) -> Option<(&str, &str)> {
+    let pos = field.find(": ")?;
+
+    Some((field[..pos].trim(), field[pos + 2..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugInfo, DebugInfos, Location, Metadata};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            super::parse(
+                r#"!3 = !DIFile(filename: "src/main.rs", directory: "/home/japaric/app")"#
+            ),
+            Some(Metadata {
+                id: 3,
+                node: DebugInfo::File {
+                    filename: "src/main.rs",
+                    directory: Some("/home/japaric/app"),
+                },
+            })
+        );
+
+        let mut dbg = DebugInfos::default();
+        for line in &[
+            r#"!3 = !DIFile(filename: "src/main.rs", directory: "/home/japaric/app")"#,
+            r#"!4 = !DIFile(filename: "/rustc/library/core/src/fmt/mod.rs", directory: "/home/japaric/app")"#,
+            r#"!12 = distinct !DISubprogram(name: "main", scope: !3, file: !3, line: 10, unit: !0)"#,
+            r#"!13 = distinct !DISubprogram(name: "fmt", scope: !4, file: !4, line: 20, unit: !0)"#,
+        ] {
+            dbg.insert(super::parse(line).unwrap());
+        }
+        assert_eq!(
+            dbg.subprogram(12).unwrap().path(),
+            "/home/japaric/app/src/main.rs"
+        );
+        // absolute paths are left as they are
+        assert_eq!(
+            dbg.subprogram(13).unwrap().path(),
+            "/rustc/library/core/src/fmt/mod.rs"
+        );
+
+        assert_eq!(
+            super::parse(
+                r#"!12 = distinct !DISubprogram(name: "main", linkageName: "_ZN3app4main17h3337355bfdc88d96E", scope: !3, file: !3, line: 10, type: !13, isLocal: false, isDefinition: true, scopeLine: 10, flags: DIFlagPrototyped, isOptimized: true, unit: !0, templateParams: !4, retainedNodes: !4)"#
+            ),
+            Some(Metadata {
+                id: 12,
+                node: DebugInfo::Subprogram {
+                    file: Some(3),
+                    line: Some(10),
+                },
+            })
+        );
+
+        assert_eq!(
+            super::parse("!20 = !DILocation(line: 14, column: 5, scope: !21, inlinedAt: !22)"),
+            Some(Metadata {
+                id: 20,
+                node: DebugInfo::Location {
+                    line: 14,
+                    column: Some(5),
+                    scope: 21,
+                    inlined_at: Some(22),
+                },
+            })
+        );
+
+        assert_eq!(
+            super::parse(
+                "!21 = distinct !DILexicalBlock(scope: !12, file: !3, line: 11, column: 5)"
+            ),
+            Some(Metadata {
+                id: 21,
+                node: DebugInfo::LexicalBlock {
+                    scope: 12,
+                    file: Some(3),
+                },
+            })
+        );
+
+        assert_eq!(super::parse("!llvm.dbg.cu = !{!0}"), None);
+        assert_eq!(super::parse("!4 = !{}"), None);
+        assert_eq!(super::parse("!5 = !DIExpression()"), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let mut dbg = DebugInfos::default();
+        for line in &[
+            r#"!3 = !DIFile(filename: "src/main.rs", directory: "/home/japaric/app")"#,
+            r#"!12 = distinct !DISubprogram(name: "main", scope: !3, file: !3, line: 10, unit: !0)"#,
+            r#"!30 = distinct !DISubprogram(name: "foo", scope: !3, file: !3, line: 30, unit: !0)"#,
+            "!21 = distinct !DILexicalBlock(scope: !12, file: !3, line: 11, column: 5)",
+            "!22 = !DILocation(line: 14, column: 9, scope: !21)",
+            "!20 = !DILocation(line: 32, column: 5, scope: !30, inlinedAt: !22)",
+        ] {
+            dbg.insert(super::parse(line).unwrap());
+        }
+
+        assert_eq!(
+            dbg.subprogram(12),
+            Some(Location {
+                file: "src/main.rs",
+                directory: Some("/home/japaric/app"),
+                line: 10,
+                column: None,
+            })
+        );
+
+        // call that `foo` makes, after being inlined into `main`
+        assert_eq!(
+            dbg.call_site(20),
+            Some(Location {
+                file: "src/main.rs",
+                directory: Some("/home/japaric/app"),
+                line: 14,
+                column: Some(9),
+            })
+        );
+
+        assert_eq!(
+            dbg.call_site(20).unwrap().to_string(),
+            "/home/japaric/app/src/main.rs:14:9"
+        );
+        assert_eq!(dbg.subprogram(21), None);
+    }
+}
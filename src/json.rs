@@ -0,0 +1,176 @@
+use std::{borrow::Cow, io};
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::{ir, CallGraph as Cg, Local, Max};
+
+/// Version of the JSON schema
+///
+/// This must be bumped every time a field is removed or its meaning changes. Adding new fields is
+/// a backwards compatible change.
+pub const VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+pub struct CallGraph<'a> {
+    pub version: u32,
+    #[serde(borrow)]
+    pub nodes: Vec<Node<'a>>,
+    #[serde(borrow)]
+    pub edges: Vec<Edge<'a>>,
+    // Strongly Connected Components (cycles) as lists of node `id`s
+    pub sccs: Vec<Vec<usize>>,
+    // annotations the results depend on
+    #[serde(borrow, default)]
+    pub assumptions: Vec<Cow<'a, str>>,
+}
+
+impl<'a> CallGraph<'a> {
+    /// Parses a JSON document previously produced by `json`
+    pub fn parse(json: &'a str) -> Result<Self, failure::Error> {
+        let cg: CallGraph = serde_json::from_str(json)?;
+
+        if cg.version != VERSION {
+            return Err(format_err!(
+                "unsupported JSON schema version {} (expected version {})",
+                cg.version,
+                VERSION
+            ));
+        }
+
+        Ok(cg)
+    }
+}
+
+impl<'a> From<&'a Cg<'_>> for CallGraph<'a> {
+    fn from(cg: &'a Cg) -> Self {
+        let g = &cg.graph;
+
+        CallGraph {
+            version: VERSION,
+            nodes: g
+                .raw_nodes()
+                .iter()
+                .enumerate()
+                .map(|(id, node)| {
+                    let node = &node.weight;
+
+                    Node {
+                        id,
+                        name: Cow::Borrowed(&node.name),
+                        demangled: Cow::Borrowed(&node.demangled),
+                        local: node.local,
+                        max: node.max,
+                        max_callee: node.max_callee.map(|idx| idx.index()),
+                        dashed: node.dashed,
+                        location: node.location.map(Location::from),
+                        origin: node.origin.map(Cow::Borrowed),
+                    }
+                })
+                .collect(),
+            edges: g
+                .raw_edges()
+                .iter()
+                .map(|edge| Edge {
+                    source: edge.source().index(),
+                    target: edge.target().index(),
+                    location: edge.weight.location.map(Location::from),
+                })
+                .collect(),
+            sccs: cg
+                .cycles
+                .iter()
+                .map(|cycle| cycle.iter().map(|node| node.index()).collect())
+                .collect(),
+            assumptions: cg
+                .assumptions
+                .iter()
+                .map(|assumption| Cow::Borrowed(&**assumption))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Node<'a> {
+    // index into `CallGraph.nodes`
+    pub id: usize,
+    // symbol name as it appears in the ELF; fictitious nodes use their signature here
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    // name used in the dot graph
+    #[serde(borrow)]
+    pub demangled: Cow<'a, str>,
+    pub local: Local,
+    // `None` when the max stack usage analysis was skipped
+    pub max: Option<Max>,
+    // `id` of the callee that realizes `max`
+    pub max_callee: Option<usize>,
+    // fictitious nodes (`fn` pointers / trait objects) are drawn with dashed borders
+    pub dashed: bool,
+    // where the function is defined; `None` when there's no debug information about it
+    #[serde(borrow, default)]
+    pub location: Option<Location<'a>>,
+    // archive the stack usage information comes from; `None` when it comes from the program
+    #[serde(borrow, default)]
+    pub origin: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Edge<'a> {
+    pub source: usize,
+    pub target: usize,
+    // where the call is made; `None` when there's no debug information about it
+    #[serde(borrow, default)]
+    pub location: Option<Location<'a>>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Location<'a> {
+    #[serde(borrow)]
+    pub file: Cow<'a, str>,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+impl<'a> From<ir::Location<'a>> for Location<'a> {
+    fn from(location: ir::Location<'a>) -> Self {
+        Location {
+            file: location.path(),
+            line: location.line,
+            column: location.column,
+        }
+    }
+}
+
+/// Writes the call graph as a JSON document
+pub fn json<W>(cg: &Cg, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    serde_json::to_writer_pretty(&mut stdout, &CallGraph::from(cg))?;
+    writeln!(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Local, Max};
+
+    #[test]
+    fn stack() {
+        assert_eq!(
+            serde_json::to_string(&Local::Exact(8)).unwrap(),
+            r#"{"kind":"exact","value":8}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&Local::Unknown).unwrap(),
+            r#"{"kind":"unknown"}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&Max::LowerBound(24)).unwrap(),
+            r#"{"kind":"lower_bound","value":24}"#
+        );
+    }
+}
@@ -0,0 +1,2586 @@
+//! Whole program static stack analysis
+//!
+//! This is the library behind the `cargo call-stack` subcommand. It takes the artifacts produced
+//! by `rustc` -- the linked ELF file, the LLVM-IR of the whole program and the object file that
+//! contains the `.stack_sizes` section -- and produces a call graph annotated with stack usage
+//! information.
+//!
+//! ``` ignore
+//! let cg = cargo_call_stack::analyze(&Input {
+//!     elf: &elf,
+//!     ll: &ll,
+//!     obj: &obj,
+//!     libs: &[],
+//!     target: "thumbv7m-none-eabi",
+//!     start: Some("main"),
+//!     annotations: &Annotations::default(),
+//!     builtins: &Builtins::bundled("thumbv7m-none-eabi"),
+//!     toolchain: None,
+//! })?;
+//!
+//! cargo_call_stack::dot::dot(&cg, io::stdout())?;
+//! ```
+
+use core::{cmp, fmt, ops, str};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    process::Command,
+};
+
+use ar::Archive;
+use petgraph::{
+    algo,
+    graph::{DiGraph, Graph, NodeIndex},
+    visit::{Dfs, EdgeRef, Reversed, Topo},
+    Direction,
+};
+use serde::{Deserialize, Serialize};
+use xmas_elf::{header::Class, sections::SectionData, symbol_table::Entry, ElfFile};
+
+use crate::{
+    annotations::{Annotations, Callees},
+    builtins::Builtins,
+    ir::{DebugInfos, FnSig, Item, Location, Stmt, Type},
+    thumb::{Arch, Tag},
+};
+
+// like `log::warn!` but records the message as a `Diagnostic` of the given `Rule`;
+// `function = $name` names the function the problem is about
+macro_rules! warning {
+    ($diagnostics:expr, $rule:expr, function = $function:expr, $($arg:tt)*) => {
+        $diagnostics.push(crate::Diagnostic {
+            level: crate::Level::Warning,
+            rule: $rule,
+            function: Some($function.to_string()),
+            message: format!($($arg)*),
+        })
+    };
+    ($diagnostics:expr, $rule:expr, $($arg:tt)*) => {
+        $diagnostics.push(crate::Diagnostic {
+            level: crate::Level::Warning,
+            rule: $rule,
+            function: None,
+            message: format!($($arg)*),
+        })
+    };
+}
+
+// like `log::error!` but records the message as a `Diagnostic`; see `warning!`
+macro_rules! error {
+    ($diagnostics:expr, $rule:expr, function = $function:expr, $($arg:tt)*) => {
+        $diagnostics.push(crate::Diagnostic {
+            level: crate::Level::Error,
+            rule: $rule,
+            function: Some($function.to_string()),
+            message: format!($($arg)*),
+        })
+    };
+    ($diagnostics:expr, $rule:expr, $($arg:tt)*) => {
+        $diagnostics.push(crate::Diagnostic {
+            level: crate::Level::Error,
+            rule: $rule,
+            function: None,
+            message: format!($($arg)*),
+        })
+    };
+}
+
+pub mod aarch64;
+pub mod annotations;
+pub mod baseline;
+pub mod budget;
+pub mod builtins;
+pub mod dot;
+pub mod folded;
+pub mod html;
+pub mod ir;
+pub mod json;
+pub mod path;
+pub mod policy;
+pub mod preemption;
+pub mod riscv;
+pub mod sarif;
+pub mod summary;
+pub mod thumb;
+
+mod address_taken;
+
+/// Inputs to the analysis
+pub struct Input<'a> {
+    /// The linked program
+    pub elf: &'a [u8],
+    /// LLVM-IR of the whole program (`--emit=llvm-ir -C lto`)
+    pub ll: &'a str,
+    /// Object file that contains the stack usage information (`--emit=obj -Z emit-stack-sizes`)
+    pub obj: &'a [u8],
+    /// Other object files that may contain stack usage information (see `sysroot_objects`)
+    pub libs: &'a [Object],
+    /// Target triple for which the program was compiled
+    pub target: &'a str,
+    /// Consider only the call graph that starts from this function
+    pub start: Option<&'a str>,
+    /// Information provided by the user (indirect call targets, stack usage, etc.)
+    pub annotations: &'a Annotations,
+    /// Stack usage and signatures of the `compiler-builtins` functions
+    pub builtins: &'a Builtins,
+    /// Version of the Rust toolchain that compiled the program (e.g. `1.33.0`); the stack usage
+    /// in `builtins` is ignored if it was measured on a different version. `None` skips this check
+    pub toolchain: Option<&'a str>,
+}
+
+/// An object file that's linked into the program but is not part of the program's LLVM-IR
+pub struct Object {
+    /// The archive the object file comes from (e.g. `libcore-0123456789abcdef.rlib`)
+    pub origin: String,
+    pub bytes: Vec<u8>,
+}
+
+/// The result of the analysis
+pub struct CallGraph<'a> {
+    /// Nodes are functions and edges are "calls" relationships
+    pub graph: Graph<Node<'a>, Edge<'a>>,
+    /// Strongly Connected Components (cycles) in the call graph
+    pub cycles: Vec<Vec<NodeIndex>>,
+    /// Stack usage of each cycle, excluding the stack usage of its neighbors; one per `cycles`
+    /// element
+    pub cycle_locals: Vec<Max>,
+    /// The node that corresponds to `Input.start`, if it was found
+    pub start: Option<NodeIndex>,
+    /// Entries of the vector table (Cortex-M only)
+    pub vectors: Vec<preemption::Vector<'a>>,
+    /// Problems found during the analysis
+    pub diagnostics: Vec<Diagnostic>,
+    /// Annotations the results depend on, in human readable form
+    pub assumptions: Vec<String>,
+}
+
+/// A problem found during the analysis
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    /// The kind of problem
+    pub rule: Rule,
+    /// The function the problem is about, if any; a name `find` understands
+    pub function: Option<String>,
+    pub message: String,
+}
+
+/// Severity of a `Diagnostic`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// The kind of problem a `Diagnostic` reports
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Rule {
+    /// A function has no stack usage information
+    NoStackInfo,
+    /// The stack usage of a `compiler-builtins` function comes from, or was ignored in, the
+    /// builtins file
+    Builtins,
+    /// The analysis assumes something about `asm!` blocks or LLVM intrinsics it can't check
+    Assumption,
+    /// LLVM's stack usage information doesn't match the machine code
+    StackMismatch,
+    /// A function has no, or unparseable, type information
+    TypeInfo,
+    /// The callees of an indirect function call couldn't be narrowed down or bounded
+    IndirectCall,
+    /// A cycle in the call graph has no recursion depth bound
+    Recursion,
+    /// An annotation couldn't be applied
+    Annotations,
+    /// A stack budget was exceeded or couldn't be checked
+    Budget,
+    /// A change relative to the baseline
+    Baseline,
+    /// A problem with the system-wide (preemption) analysis
+    Preemption,
+    /// A problem with the inputs (e.g. a missing section or start point)
+    Input,
+    /// The output is incomplete
+    Output,
+    /// Something that looks like a bug in this tool
+    Internal,
+}
+
+impl Rule {
+    /// All the rules
+    pub const ALL: &'static [Rule] = &[
+        Rule::NoStackInfo,
+        Rule::Builtins,
+        Rule::Assumption,
+        Rule::StackMismatch,
+        Rule::TypeInfo,
+        Rule::IndirectCall,
+        Rule::Recursion,
+        Rule::Annotations,
+        Rule::Budget,
+        Rule::Baseline,
+        Rule::Preemption,
+        Rule::Input,
+        Rule::Output,
+        Rule::Internal,
+    ];
+
+    /// Stable code (e.g. `CS001`); codes are never reused
+    pub fn code(self) -> &'static str {
+        match self {
+            Rule::NoStackInfo => "CS001",
+            Rule::Builtins => "CS002",
+            Rule::Assumption => "CS003",
+            Rule::StackMismatch => "CS004",
+            Rule::TypeInfo => "CS005",
+            Rule::IndirectCall => "CS006",
+            Rule::Recursion => "CS007",
+            Rule::Annotations => "CS008",
+            Rule::Budget => "CS009",
+            Rule::Baseline => "CS010",
+            Rule::Preemption => "CS011",
+            Rule::Input => "CS012",
+            Rule::Output => "CS013",
+            Rule::Internal => "CS014",
+        }
+    }
+
+    /// Looks up a rule by its code (`CS001`) or its identifier (`no-stack-info`)
+    pub fn parse(s: &str) -> Option<Rule> {
+        Rule::ALL
+            .iter()
+            .cloned()
+            .find(|rule| rule.code().eq_ignore_ascii_case(s) || rule.id() == s)
+    }
+
+    /// Stable identifier (e.g. `no-stack-info`)
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::NoStackInfo => "no-stack-info",
+            Rule::Builtins => "builtins",
+            Rule::Assumption => "assumption",
+            Rule::StackMismatch => "stack-mismatch",
+            Rule::TypeInfo => "type-info",
+            Rule::IndirectCall => "indirect-call",
+            Rule::Recursion => "recursion",
+            Rule::Annotations => "annotations",
+            Rule::Budget => "budget",
+            Rule::Baseline => "baseline",
+            Rule::Preemption => "preemption",
+            Rule::Input => "input",
+            Rule::Output => "output",
+            Rule::Internal => "internal",
+        }
+    }
+
+    /// One-line description
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::NoStackInfo => "A function has no stack usage information",
+            Rule::Builtins => "Stack usage information injected from the builtins file",
+            Rule::Assumption => "An assumption about inline assembly or LLVM intrinsics",
+            Rule::StackMismatch => "LLVM's stack usage information doesn't match the machine code",
+            Rule::TypeInfo => "A function has no or unparseable type information",
+            Rule::IndirectCall => "The callees of an indirect function call couldn't be bounded",
+            Rule::Recursion => "A cycle in the call graph has no recursion depth bound",
+            Rule::Annotations => "An annotation couldn't be applied",
+            Rule::Budget => "A stack budget was exceeded or couldn't be checked",
+            Rule::Baseline => "Stack usage changed relative to the baseline",
+            Rule::Preemption => "A problem with the system-wide analysis",
+            Rule::Input => "A problem with the inputs of the analysis",
+            Rule::Output => "The output is incomplete",
+            Rule::Internal => "A possible bug in cargo-call-stack",
+        }
+    }
+}
+
+/// Extracts the object files of all the precompiled crates (`core`, `alloc`, `compiler-builtins`,
+/// etc.) from the sysroot
+///
+/// These object files may contain stack usage information about the functions of those crates
+/// that are linked into the program without going through LTO. Returns `None` if the `target` is
+/// not installed.
+pub fn sysroot_objects(target: &str) -> Result<Option<Vec<Object>>, failure::Error> {
+    let sysroot_nl = String::from_utf8(
+        Command::new("rustc")
+            .args(["--print", "sysroot"])
+            .output()?
+            .stdout,
+    )?;
+    // remove trailing newline
+    let sysroot = Path::new(sysroot_nl.trim_end());
+    let libdir = sysroot.join("lib/rustlib").join(target).join("lib");
+
+    if !libdir.exists() {
+        return Ok(None);
+    }
+
+    let mut paths = vec![];
+    for entry in fs::read_dir(libdir)? {
+        let path = entry?.path();
+
+        if path.extension().map(|ext| ext == "rlib").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    // for reproducible results
+    paths.sort();
+
+    let mut objects = vec![];
+    for path in paths {
+        objects.extend(archive_objects(&path)?);
+    }
+
+    Ok(Some(objects))
+}
+
+/// Extracts the object files from an archive (`.a` or `.rlib`)
+///
+/// Members that are not ELF object files (e.g. Rust metadata or LLVM bitcode) are skipped.
+pub fn archive_objects(path: &Path) -> Result<Vec<Object>, failure::Error> {
+    let origin = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut ar = Archive::new(File::open(path)?);
+    let mut objects = vec![];
+    while let Some(entry) = ar.next_entry() {
+        let mut entry = entry?;
+
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+
+        if bytes.starts_with(b"\x7fELF") {
+            objects.push(Object {
+                origin: origin.clone(),
+                bytes,
+            });
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Builds the call graph of the program and computes the stack usage of each function
+pub fn analyze<'a>(input: &Input<'a>) -> Result<CallGraph<'a>, failure::Error> {
+    let mut diagnostics = vec![];
+    let mut assumptions = vec![];
+    let annotations = input.annotations;
+
+    let items = crate::ir::parse(input.ll)?;
+    let mut defines = HashMap::new();
+    let mut declares = HashMap::new();
+    // global variable -> symbols its initializer refers to
+    let mut globals = HashMap::new();
+    // global variables that can be written to at runtime
+    let mut mutable_globals = HashSet::new();
+    let mut dbg = DebugInfos::default();
+    for item in items {
+        match item {
+            Item::Define(def) => {
+                defines.insert(def.name, def);
+            }
+
+            Item::Declare(decl) => {
+                declares.insert(decl.name, decl);
+            }
+
+            Item::Global(global) => {
+                if global.mutable {
+                    mutable_globals.insert(global.name);
+                }
+                globals.insert(global.name, global.refs);
+            }
+
+            Item::Metadata(Some(metadata)) => dbg.insert(metadata),
+
+            _ => {}
+        }
+    }
+
+    // we know how to analyze the machine code in the ELF file for these targets thus we have more
+    // information and need less LLVM-IR hacks
+    let target_ = Target::from_triple(input.target);
+    let builtins = input.builtins;
+    // the stack usage in `builtins` is only valid for the toolchain it was measured on
+    let fresh_builtins = input
+        .toolchain
+        .map(|toolchain| builtins.is_fresh(toolchain))
+        .unwrap_or(true);
+    // used for the functions that are missing from `builtins`
+    let signatures = Builtins::signatures();
+    // size of pointers, in bits
+    let word = match ElfFile::new(input.elf).map(|elf| elf.header.pt1.class()) {
+        Ok(Class::SixtyFour) => 64,
+        _ => 32,
+    };
+
+    // extract stack size information
+    // the `.o` file doesn't have address information so we just keep the stack usage information
+    let mut stack_sizes: HashMap<_, _> = stack_sizes::analyze_object(input.obj)?
+        .into_iter()
+        .map(|(name, stack)| (name.to_owned(), stack))
+        .collect();
+
+    // extract stack usage info from other object files (e.g. `libcore.rlib`); the program's own
+    // object file takes precedence
+    // symbol name -> archive the stack usage information comes from
+    let mut origins = HashMap::new();
+    for lib in input.libs {
+        match stack_sizes::analyze_object(&lib.bytes) {
+            Ok(sizes) => {
+                for (name, stack) in sizes {
+                    if !stack_sizes.contains_key(name) {
+                        stack_sizes.insert(name.to_owned(), stack);
+                        origins.insert(name.to_owned(), &*lib.origin);
+                    }
+                }
+            }
+
+            Err(e) => warning!(
+                diagnostics,
+                Rule::Input,
+                "couldn't extract stack usage information from an object file in `{}`: {}",
+                lib.origin,
+                e
+            ),
+        }
+    }
+
+    // extract list of "live" symbols (symbols that have not been GC-ed by the linker)
+    // this time we use the ELF and not the object file
+    let mut symbols = stack_sizes::analyze_executable(input.elf)?;
+
+    // clear the thumb bit
+    if target_.is_thumb() {
+        symbols.defined = symbols
+            .defined
+            .into_iter()
+            .map(|(k, v)| (k & !1, v))
+            .collect();
+    }
+
+    // remove version strings from undefined symbols
+    symbols.undefined = symbols
+        .undefined
+        .into_iter()
+        .map(|sym| {
+            if let Some(name) = sym.rsplit("@@").nth(1) {
+                name
+            } else {
+                sym
+            }
+        })
+        .collect();
+
+    let mut g = DiGraph::<Node, Edge>::new();
+    let mut indices = BTreeMap::<Cow<str>, _>::new();
+
+    let mut indirects: HashMap<FnSig, Indirect> = HashMap::new();
+    let mut dynamics: HashMap<FnSig, Dynamic> = HashMap::new();
+    // functions that could be called by `ArgumentV1.formatter`
+    let mut fmts = HashSet::new();
+
+    // Some functions may be aliased; we map aliases to a single name. For example, if `foo`,
+    // `bar` and `baz` all have the same address then this maps contains: `foo -> foo`, `bar -> foo`
+    // and `baz -> foo`.
+    let mut aliases = HashMap::new();
+    // whether a symbol name is ambiguous after removing the hash
+    let mut ambiguous = HashMap::<String, u32>::new();
+
+    // we do a first pass over all the definitions to collect methods in `impl Trait for Type`
+    let mut default_methods = HashSet::new();
+    for name in defines.keys() {
+        let demangled = rustc_demangle::demangle(name).to_string();
+
+        // `<crate::module::Type as crate::module::Trait>::method::hdeadbeef`
+        if demangled.starts_with("<") {
+            if let Some((_, rhs)) = demangled.split_once(" as ") {
+                // rhs = `crate::module::Trait>::method::hdeadbeef`
+                let mut parts = rhs.splitn(2, ">::");
+
+                if let (Some(trait_), Some(rhs)) = (parts.next(), parts.next()) {
+                    // trait_ = `crate::module::Trait`, rhs = `method::hdeadbeef`
+
+                    if let Some(method) = dehash(rhs) {
+                        default_methods.insert(format!("{}::{}", trait_, method));
+                    }
+                }
+            }
+        }
+    }
+
+    // add all real nodes
+    let mut has_stack_usage_info = false;
+    let mut has_untyped_symbols = false;
+    let mut addr2name = BTreeMap::new();
+    for (address, sym) in &symbols.defined {
+        let names = sym.names();
+
+        let canonical_name = if names.len() > 1 {
+            // if one of the aliases appears in the `stack_sizes` dictionary, use that
+            if let Some(needle) = names.iter().find(|name| stack_sizes.contains_key(&***name)) {
+                needle
+            } else {
+                // otherwise, pick the first name that's not a tag
+                names
+                    .iter()
+                    .filter_map(|&name| {
+                        if name == "$a" || name.starts_with("$a.") {
+                            None
+                        } else {
+                            Some(name)
+                        }
+                    })
+                    .next()
+                    .expect("UNREACHABLE")
+            }
+        } else {
+            names[0]
+        };
+
+        for name in names {
+            aliases.insert(name, canonical_name);
+        }
+
+        let _out = addr2name.insert(address, canonical_name);
+        debug_assert!(_out.is_none());
+
+        let mut stack = stack_sizes.get(canonical_name).cloned();
+        if stack.is_none() {
+            // here we inject the stack usage information we got from analyzing
+            // `libcompiler_builtins.rlib` (see `Builtins::measure`)
+            let local = builtins
+                .get(canonical_name)
+                .and_then(|builtin| builtin.local);
+
+            if let (Some(local), true) = (local, fresh_builtins) {
+                stack = Some(local);
+
+                warning!(
+                    diagnostics,
+                    Rule::Builtins,
+                    function = canonical_name,
+                    "ad-hoc: injecting stack usage information for `{}` (measured on Rust {})",
+                    canonical_name,
+                    builtins.toolchain
+                );
+            } else if let Some(local) = local {
+                warning!(
+                    diagnostics,
+                    Rule::Builtins,
+                    function = canonical_name,
+                    "ad-hoc: ignoring the stack usage of `{}` ({} bytes) because it was measured \
+                     on Rust {}, not on Rust {}; regenerate the builtins file with \
+                     `--measure-builtins`",
+                    canonical_name,
+                    local,
+                    builtins.toolchain,
+                    input.toolchain.unwrap_or("?")
+                );
+            }
+
+            if stack.is_none() && !target_.has_decoder() && !annotations.has_stack(canonical_name) {
+                warning!(
+                    diagnostics,
+                    Rule::NoStackInfo,
+                    function = canonical_name,
+                    "no stack usage information for `{}`",
+                    canonical_name
+                );
+            }
+        } else {
+            has_stack_usage_info = true;
+        }
+
+        let demangled = rustc_demangle::demangle(canonical_name).to_string();
+        if let Some(dehashed) = dehash(&demangled) {
+            *ambiguous.entry(dehashed.to_string()).or_insert(0) += 1;
+        }
+
+        let idx = g.add_node(Node(canonical_name, stack, false));
+        indices.insert(canonical_name.into(), idx);
+        g[idx].origin = origins.get(canonical_name).cloned();
+        if stack.is_none() {
+            g[idx].unknown = Some(Unknown::NoInfo);
+        }
+
+        // trait methods look like `<crate::module::Type as crate::module::Trait>::method::h$hash`
+        // default trait methods look like `crate::module::Trait::method::h$hash`
+        let is_trait_method = demangled.starts_with("<") && demangled.contains(" as ") || {
+            dehash(&demangled)
+                .map(|path| default_methods.contains(path))
+                .unwrap_or(false)
+        };
+
+        if let Some(def) = names.iter().filter_map(|name| defines.get(name)).next() {
+            // if the signature is `fn(&_, &mut fmt::Formatter) -> fmt::Result`
+            match (&def.sig.inputs[..], def.sig.output.as_ref()) {
+                ([Type::Pointer(..), Type::Pointer(fmt)], Some(output))
+                    if **fmt == Type::Alias("core::fmt::Formatter")
+                        && **output == Type::Integer(1) =>
+                {
+                    fmts.insert(idx);
+                }
+
+                _ => {}
+            }
+
+            let is_object_safe = is_trait_method && {
+                match def.sig.inputs.first().as_ref() {
+                    Some(Type::Pointer(ty)) => match **ty {
+                        // XXX can the receiver be a *specific* function? (e.g. `fn() {foo}`)
+                        Type::Fn(_) => false,
+
+                        _ => true,
+                    },
+                    _ => false,
+                }
+            };
+
+            if is_object_safe {
+                let mut sig = def.sig.clone();
+
+                // erase the type of the reciver
+                sig.inputs[0] = Type::erased();
+
+                dynamics.entry(sig).or_default().callees.insert(idx);
+            } else {
+                indirects
+                    .entry(def.sig.clone())
+                    .or_default()
+                    .callees
+                    .insert(idx);
+            }
+        } else if let Some(sig) = names
+            .iter()
+            .filter_map(|name| declares.get(name).and_then(|decl| decl.sig.clone()))
+            .next()
+        {
+            // sanity check (?)
+            if is_trait_method {
+                error!(
+                    diagnostics,
+                    Rule::Internal,
+                    function = canonical_name,
+                    "trait method `{}` is declared but not defined in the LLVM-IR; treating it as \
+                     a regular function",
+                    demangled
+                );
+            }
+
+            indirects.entry(sig).or_default().callees.insert(idx);
+        } else {
+            // from `compiler-builtins`
+            match builtins
+                .get(canonical_name)
+                .or_else(|| signatures.get(canonical_name))
+                .map(|builtin| &builtin.signature)
+            {
+                Some(Some(signature)) => {
+                    if let Some(sig) = builtins::signature(signature, word) {
+                        indirects.entry(sig).or_default().callees.insert(idx);
+                    } else {
+                        has_untyped_symbols = true;
+                        warning!(
+                            diagnostics,
+                            Rule::TypeInfo,
+                            function = canonical_name,
+                            "couldn't parse the signature of `{}`: `{}`",
+                            canonical_name,
+                            signature
+                        );
+                    }
+                }
+
+                Some(None) => {
+                    // these subroutines don't use a standard calling convention and are impossible
+                    // to call from Rust code (they can be called via `asm!` though; e.g.
+                    // `__aeabi_uldivmod`). Their entry in the builtins file suppresses the warning
+                    // below
+                }
+
+                None => {
+                    has_untyped_symbols = true;
+                    warning!(
+                        diagnostics,
+                        Rule::TypeInfo,
+                        function = canonical_name,
+                        "no type information for `{}`",
+                        canonical_name
+                    );
+                }
+            }
+        }
+    }
+
+    let annotated_calls = Callees::resolve(annotations, &g, &mut diagnostics);
+    // callers that were annotated with the callees of their indirect function calls
+    let mut callers_seen = HashSet::new();
+    // stack usage of `asm!` blocks (from annotations); caller -> bytes
+    let mut asm_stacks = HashMap::<NodeIndex, u64>::new();
+    // functions that contain `asm!` blocks
+    let mut asm_callers = HashSet::new();
+
+    // to avoid printing several warnings about the same thing
+    let mut asm_seen = HashSet::new();
+    let mut llvm_seen = HashSet::new();
+    // add edges
+    let mut edges: HashMap<_, HashSet<_>> = HashMap::new(); // NodeIdx -> [NodeIdx]
+    let mut defined = HashSet::new(); // functions that are `define`-d in the LLVM-IR
+    let mut refs = HashMap::<NodeIndex, Vec<&str>>::new(); // NodeIdx -> symbols it refers to
+    let mut stores = HashSet::new(); // symbols written to memory
+    let mut args = HashSet::new(); // symbols passed as arguments to other functions
+    for define in defines.values() {
+        let (caller, callees_seen) = if let Some(canonical_name) = aliases.get(&define.name) {
+            defined.insert(*canonical_name);
+
+            let idx = indices[*canonical_name];
+            g[idx].location = define.dbg.and_then(|id| dbg.subprogram(id));
+            refs.entry(idx).or_default().extend(&define.refs);
+            stores.extend(&define.stores);
+            args.extend(&define.args);
+            (idx, edges.entry(idx).or_default())
+        } else {
+            // this symbol was GC-ed by the linker, skip
+            continue;
+        };
+
+        for stmt in &define.stmts {
+            match stmt {
+                Stmt::Asm(expr) => {
+                    let stack = annotations.asm(expr);
+                    asm_callers.insert(caller);
+
+                    if !asm_seen.contains(expr) {
+                        asm_seen.insert(expr);
+
+                        if let Some(stack) = stack {
+                            assumptions
+                                .push(format!("asm!(\"{}\") uses {} bytes of stack", expr, stack));
+                        } else {
+                            warning!(
+                                diagnostics,
+                                Rule::Assumption,
+                                function = g[caller].name,
+                                "assuming that asm!(\"{}\") does *not* use the stack",
+                                expr
+                            );
+                        }
+                    }
+
+                    if let Some(stack) = stack {
+                        let asm_stack = asm_stacks.entry(caller).or_default();
+                        *asm_stack = cmp::max(*asm_stack, stack);
+                    }
+                }
+
+                // this is basically `(mem::transmute<*const u8, fn()>(&__some_symbol))()`
+                Stmt::BitcastCall(sym, loc) => {
+                    // XXX we have some type information for this call but it's unclear if we should
+                    // try harder -- does this ever occur in pure Rust programs?
+
+                    let sym = sym.expect("BUG? unnamed symbol is being invoked");
+                    let callee = if let Some(idx) = indices.get(sym) {
+                        *idx
+                    } else {
+                        if !annotations.has_stack(sym) {
+                            warning!(
+                                diagnostics,
+                                Rule::NoStackInfo,
+                                function = sym,
+                                "no stack information for `{}`",
+                                sym
+                            );
+                        }
+
+                        let idx = g.add_node(Node(sym, None, false));
+                        g[idx].unknown = Some(Unknown::Undefined);
+                        indices.insert(Cow::Borrowed(sym), idx);
+                        idx
+                    };
+
+                    g.add_edge(caller, callee, Edge::at(&dbg, *loc));
+                }
+
+                Stmt::DirectCall(func, loc) => {
+                    match *func {
+                        // no-op / debug-info
+                        "llvm.dbg.value" => continue,
+                        "llvm.dbg.declare" => continue,
+
+                        // no-op / compiler-hint
+                        "llvm.assume" => continue,
+
+                        // lowers to a single instruction
+                        "llvm.trap" => continue,
+
+                        _ => {}
+                    }
+
+                    // no-op / compiler-hint
+                    if func.starts_with("llvm.lifetime.start")
+                        || func.starts_with("llvm.lifetime.end")
+                    {
+                        continue;
+                    }
+
+                    let edge = Edge::at(&dbg, *loc);
+                    let mut call = |callee| {
+                        if !callees_seen.contains(&callee) {
+                            g.add_edge(caller, callee, edge);
+                            callees_seen.insert(callee);
+                        }
+                    };
+
+                    if target_.has_decoder() && func.starts_with("llvm.") {
+                        // we'll analyze the machine code in the ELF file to figure out what these
+                        // lower to
+                        continue;
+                    }
+
+                    // TODO? consider alignment and `value` argument to only include one edge
+                    // TODO? consider the `len` argument to elide the call to `*mem*`
+                    if func.starts_with("llvm.memcpy.") {
+                        if let Some(callee) = indices.get("memcpy") {
+                            call(*callee);
+                        }
+
+                        // ARMv7-R and the like use these
+                        if let Some(callee) = indices.get("__aeabi_memcpy") {
+                            call(*callee);
+                        }
+
+                        if let Some(callee) = indices.get("__aeabi_memcpy4") {
+                            call(*callee);
+                        }
+
+                        continue;
+                    }
+
+                    // TODO? consider alignment and `value` argument to only include one edge
+                    // TODO? consider the `len` argument to elide the call to `*mem*`
+                    if func.starts_with("llvm.memset.") || func.starts_with("llvm.memmove.") {
+                        if let Some(callee) = indices.get("memset") {
+                            call(*callee);
+                        }
+
+                        // ARMv7-R and the like use these
+                        if let Some(callee) = indices.get("__aeabi_memset") {
+                            call(*callee);
+                        }
+
+                        if let Some(callee) = indices.get("__aeabi_memset4") {
+                            call(*callee);
+                        }
+
+                        if let Some(callee) = indices.get("memclr") {
+                            call(*callee);
+                        }
+
+                        if let Some(callee) = indices.get("__aeabi_memclr") {
+                            call(*callee);
+                        }
+
+                        if let Some(callee) = indices.get("__aeabi_memclr4") {
+                            call(*callee);
+                        }
+
+                        continue;
+                    }
+
+                    // XXX unclear whether these produce library calls on some platforms or not
+                    if func.starts_with("llvm.bswap.")
+                        | func.starts_with("llvm.ctlz.")
+                        | func.starts_with("llvm.uadd.with.overflow.")
+                        | func.starts_with("llvm.umul.with.overflow.")
+                    {
+                        if !llvm_seen.contains(func) {
+                            llvm_seen.insert(func);
+                            warning!(
+                                diagnostics,
+                                Rule::Assumption,
+                                "assuming that `{}` directly lowers to machine code",
+                                func
+                            );
+                        }
+
+                        continue;
+                    }
+
+                    if func.starts_with("llvm.") {
+                        if !llvm_seen.contains(func) {
+                            llvm_seen.insert(func);
+                            error!(
+                                diagnostics,
+                                Rule::Internal,
+                                "unhandled LLVM intrinsic `{}`; assuming it directly lowers to \
+                                 machine code",
+                                func
+                            );
+                        }
+
+                        continue;
+                    }
+
+                    // use canonical name
+                    let callee = if let Some(canon) = aliases.get(func) {
+                        indices[*canon]
+                    } else {
+                        if !symbols.undefined.contains(func) {
+                            error!(
+                                diagnostics,
+                                Rule::Internal,
+                                function = g[caller].name,
+                                "`{}` calls `{}`, which is not in the ELF file; assuming it's an \
+                                 undefined symbol",
+                                g[caller].demangled,
+                                func
+                            );
+                        }
+
+                        if let Some(idx) = indices.get(*func) {
+                            *idx
+                        } else {
+                            let idx = g.add_node(Node(*func, None, false));
+                            g[idx].unknown = Some(Unknown::Undefined);
+                            indices.insert((*func).into(), idx);
+
+                            idx
+                        }
+                    };
+
+                    if !callees_seen.contains(&callee) {
+                        callees_seen.insert(callee);
+                        g.add_edge(caller, callee, edge);
+                    }
+                }
+
+                Stmt::IndirectCall(sig, loc) => {
+                    let edge = Edge::at(&dbg, *loc);
+
+                    if let Some(annotated) = annotated_calls.callers.get(&caller) {
+                        // the user told us which functions this caller can reach
+                        callers_seen.insert(caller);
+
+                        for callee in annotated {
+                            if !callees_seen.contains(callee) {
+                                callees_seen.insert(*callee);
+                                g.add_edge(caller, *callee, edge);
+                            }
+                        }
+                    } else if sig
+                        .inputs
+                        .first()
+                        .map(|ty| ty.has_been_erased())
+                        .unwrap_or(false)
+                    {
+                        // dynamic dispatch
+                        let dynamic = dynamics.entry(sig.clone()).or_default();
+
+                        dynamic.called = true;
+                        dynamic.callers.entry(caller).or_insert(edge);
+                    } else {
+                        let indirect = indirects.entry(sig.clone()).or_default();
+
+                        indirect.called = true;
+                        indirect.callers.entry(caller).or_insert(edge);
+                    }
+                }
+
+                Stmt::Label | Stmt::Comment | Stmt::Other => {}
+            }
+        }
+    }
+
+    // here we parse the machine code in the ELF file to find out edges that don't appear in the
+    // LLVM-IR (e.g. `fadd` operation, `call llvm.umul.with.overflow`, etc.) or are difficult to
+    // disambiguate from the LLVM-IR (e.g. does this `llvm.memcpy` lower to a call to
+    // `__aebi_memcpy`, a call to `__aebi_memcpy4` or machine instructions?)
+    if target_.has_decoder() {
+        let elf = ElfFile::new(input.elf).map_err(failure::err_msg)?;
+        let mut tags = vec![];
+        if target_.is_thumb() {
+            let sect = elf.find_section_by_name(".symtab").expect("UNREACHABLE");
+            tags = match sect.get_data(&elf).unwrap() {
+                SectionData::SymbolTable32(entries) => entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let addr = entry.value() as u32;
+                        entry.get_name(&elf).ok().and_then(|name| {
+                            if name.starts_with("$d") {
+                                Some((addr, Tag::Data))
+                            } else if name.starts_with("$t") {
+                                Some((addr, Tag::Thumb))
+                            } else {
+                                None
+                            }
+                        })
+                    })
+                    .collect(),
+                _ => unreachable!(),
+            };
+
+            tags.sort_by_key(|tag| tag.0);
+        }
+
+        if let Some(sect) = elf.find_section_by_name(".text") {
+            let mut sections = vec![(sect.address(), sect.raw_data(&elf), false)];
+
+            // on ARMv8-M the linker places the Secure gateway veneers of the Secure entry
+            // functions (`sg` + `b.w __acle_se_foo`) in this section
+            if let Some(sect) = elf.find_section_by_name(".gnu.sgstubs") {
+                sections.push((sect.address(), sect.raw_data(&elf), true));
+            }
+
+            for (address, sym) in &symbols.defined {
+                let address = *address;
+                let canonical_name = aliases[&sym.names()[0]];
+                let mut size = sym.size();
+
+                let (stext, text, veneer) = if let Some(section) =
+                    sections.iter().find(|(start, text, _)| {
+                        address >= *start && address < start + text.len() as u64
+                    }) {
+                    *section
+                } else {
+                    // not in the `.text` (or `.gnu.sgstubs`) section
+                    continue;
+                };
+                let etext = stext + text.len() as u64;
+
+                if size == 0 && target_.is_thumb() && !veneer {
+                    // try harder at finding out the size of this symbol
+                    if let Ok(needle) = tags.binary_search_by(|tag| tag.0.cmp(&(address as u32))) {
+                        let start = tags[needle];
+                        if start.1 == Tag::Thumb {
+                            if let Some(end) = tags.get(needle + 1) {
+                                if end.1 == Tag::Thumb {
+                                    size = u64::from(end.0 - start.0);
+                                }
+                            }
+                        }
+                    }
+                } else if size == 0 {
+                    // assume the function ends where the next one starts
+                    size = symbols
+                        .defined
+                        .range(address + 1..)
+                        .next()
+                        .map(|(next, _)| cmp::min(*next, etext))
+                        .unwrap_or(etext)
+                        - address;
+                }
+
+                let start = (address - stext) as usize;
+                let end = start + size as usize;
+                let (bls, bs, indirect, modifies_sp, our_stack) =
+                    decode(target_, &text[start..end], address, &tags);
+                let caller = indices[canonical_name];
+
+                // sanity check
+                if let Some(stack) = our_stack {
+                    if (stack != 0) != modifies_sp {
+                        error!(
+                            diagnostics,
+                            Rule::Internal,
+                            function = canonical_name,
+                            "our analysis reported that `{}` both uses {} bytes of stack and it \
+                             does{} modify SP",
+                            canonical_name,
+                            stack,
+                            if !modifies_sp { " not" } else { "" }
+                        );
+                    }
+                }
+
+                // check the correctness of `modifies_sp` and `our_stack`
+                // also override LLVM's results when they appear to be wrong
+                if veneer {
+                    // the veneer has the name of the entry function so LLVM's result is that of
+                    // `__acle_se_foo`, which the veneer tail calls
+                    g[caller].local = our_stack.map(Local::Exact).unwrap_or(Local::Unknown);
+                } else if annotations.has_stack(canonical_name) && g[caller].local == Local::Unknown
+                {
+                    // the user provided this information; see below
+                } else if let Local::Exact(ref mut llvm_stack) = g[caller].local {
+                    if let Some(stack) = our_stack {
+                        if *llvm_stack == 0 && stack != 0 {
+                            // this could be a `#[naked]` + `asm!` function or `global_asm!`
+
+                            warning!(
+                                diagnostics,
+                                Rule::StackMismatch,
+                                function = canonical_name,
+                                "LLVM reported zero stack usage for `{}` but \
+                                 our analysis reported {} bytes; overriding LLVM's result",
+                                canonical_name,
+                                stack
+                            );
+
+                            *llvm_stack = stack;
+                        } else if stack > *llvm_stack && asm_callers.contains(&caller) {
+                            // LLVM doesn't account for the stack used by `asm!` blocks; that's
+                            // what `[[asm]]` annotations are for (see `asm_stacks`)
+                        } else if *llvm_stack != stack {
+                            // in all other cases our results should match
+                            error!(
+                                diagnostics,
+                                Rule::StackMismatch,
+                                function = canonical_name,
+                                "LLVM reported that `{}` uses {} bytes of stack but our analysis \
+                                 reported {} bytes; keeping LLVM's result",
+                                canonical_name,
+                                *llvm_stack,
+                                stack
+                            );
+                        }
+                    } else if (*llvm_stack != 0) != modifies_sp {
+                        error!(
+                            diagnostics,
+                            Rule::StackMismatch,
+                            function = canonical_name,
+                            "LLVM reported that `{}` uses {} bytes of stack but our analysis found \
+                             that it does{} modify the stack pointer; keeping LLVM's result",
+                            canonical_name,
+                            *llvm_stack,
+                            if modifies_sp { "" } else { " not" }
+                        );
+                    }
+                } else if let Some(stack) = our_stack {
+                    g[caller].local = Local::Exact(stack);
+                } else if !modifies_sp {
+                    // this happens when we can't follow the control flow of the function (e.g.
+                    // jump tables) and our analysis gives up (`our_stack == None`)
+                    g[caller].local = Local::Exact(0);
+                }
+
+                if g[caller].local == Local::Unknown {
+                    // neither LLVM nor our analysis could compute it
+                    g[caller].unknown = Some(Unknown::Analysis);
+                }
+
+                if g[caller].local == Local::Unknown && !annotations.has_stack(canonical_name) {
+                    warning!(
+                        diagnostics,
+                        Rule::NoStackInfo,
+                        function = canonical_name,
+                        "no stack usage information for `{}`",
+                        canonical_name
+                    );
+                }
+
+                if let (Some(annotated), true) = (annotated_calls.callers.get(&caller), indirect) {
+                    // the user told us which functions this caller can reach
+                    callers_seen.insert(caller);
+
+                    let callees_seen = edges.entry(caller).or_default();
+                    for callee in annotated {
+                        if !callees_seen.contains(callee) {
+                            callees_seen.insert(*callee);
+                            g.add_edge(caller, *callee, Edge::default());
+                        }
+                    }
+                } else if !defined.contains(canonical_name) && indirect {
+                    // this function performs an indirect function call and we have no type
+                    // information to narrow down the list of callees so inject the uncertainty
+                    // in the form of a call to an unknown function with unknown stack usage
+
+                    warning!(
+                        diagnostics,
+                        Rule::IndirectCall,
+                        function = canonical_name,
+                        "`{}` performs an indirect function call and there's \
+                         no type information about the operation",
+                        canonical_name,
+                    );
+                    let callee = g.add_node(Node("?", None, false));
+                    g[callee].unknown = Some(Unknown::IndirectCall);
+                    g.add_edge(caller, callee, Edge::default());
+                }
+
+                let callees_seen = edges.entry(caller).or_default();
+                for offset in bls {
+                    let addr = (address as i64 + offset) as u64;
+                    // address may be off by one due to the thumb bit being set
+                    let name = if let Some(name) = addr2name.get(&addr) {
+                        name
+                    } else {
+                        error!(
+                            diagnostics,
+                            Rule::Internal,
+                            function = canonical_name,
+                            "`{}` calls address {:#x} but there's no symbol at that address; \
+                             ignoring the call",
+                            canonical_name,
+                            addr
+                        );
+                        continue;
+                    };
+
+                    let callee = indices[*name];
+                    if !callees_seen.contains(&callee) {
+                        g.add_edge(caller, callee, Edge::default());
+                        callees_seen.insert(callee);
+                    }
+                }
+
+                for offset in bs {
+                    let addr = (address as i64 + offset) as u64;
+
+                    if addr >= address && addr < (address + size) {
+                        // intra-function B branches are not function calls
+                    } else {
+                        // address may be off by one due to the thumb bit being set
+                        let name = if let Some(name) = addr2name.get(&addr) {
+                            name
+                        } else {
+                            error!(
+                                diagnostics,
+                                Rule::Internal,
+                                function = canonical_name,
+                                "`{}` branches to address {:#x} but there's no symbol at that \
+                                 address; ignoring the branch",
+                                canonical_name,
+                                addr
+                            );
+                            continue;
+                        };
+
+                        let callee = indices[*name];
+                        if !callees_seen.contains(&callee) {
+                            g.add_edge(caller, callee, Edge::default());
+                            callees_seen.insert(callee);
+                        }
+                    }
+                }
+            }
+        } else {
+            error!(diagnostics, Rule::Input, ".text section not found")
+        }
+    }
+
+    for (caller, annotated) in &annotated_calls.callers {
+        let callees = annotated
+            .iter()
+            .map(|callee| format!("`{}`", g[*callee].demangled))
+            .collect::<Vec<_>>();
+
+        if callers_seen.contains(caller) {
+            assumptions.push(format!(
+                "the indirect function calls in `{}` can only reach {}",
+                g[*caller].demangled,
+                callees.join(", ")
+            ));
+        } else {
+            warning!(
+                diagnostics,
+                Rule::Annotations,
+                function = g[*caller].name,
+                "annotations: `{}` performs no indirect function calls",
+                g[*caller].demangled
+            );
+        }
+    }
+
+    // apply the stack usage annotations
+    for stack in &annotations.stack {
+        let hits = find(&g, &stack.function);
+
+        if hits.is_empty() {
+            warning!(
+                diagnostics,
+                Rule::Annotations,
+                "annotations: function `{}` is not in the call graph; ignoring it",
+                stack.function
+            );
+        }
+
+        for idx in hits {
+            let node = &mut g[idx];
+
+            if node.local == Local::Unknown {
+                node.local = Local::Exact(stack.local);
+                assumptions.push(format!(
+                    "`{}` uses {} bytes of stack",
+                    node.demangled, stack.local
+                ));
+            } else {
+                warning!(
+                    diagnostics,
+                    Rule::Annotations,
+                    function = node.name,
+                    "annotations: the stack usage of `{}` is already known ({} bytes); ignoring \
+                     the annotation",
+                    node.demangled,
+                    node.local
+                );
+            }
+        }
+    }
+
+    for (caller, asm_stack) in asm_stacks {
+        if let Local::Exact(ref mut local) = g[caller].local {
+            *local += asm_stack;
+        }
+    }
+
+    // add fictitious nodes for indirect function calls
+    if has_untyped_symbols {
+        warning!(
+            diagnostics,
+            Rule::IndirectCall,
+            "the program contains untyped, external symbols (e.g. linked in from binary blobs); \
+             indirect function calls can not be bounded"
+        );
+    }
+
+    // this is a bit weird but for some reason `ArgumentV1.formatter` sometimes lowers to different
+    // LLVM types. In theory it should always be: `i1 (*%fmt::Void, *&core::fmt::Formatter)*` but
+    // sometimes the type of the first argument is `%fmt::Void`, sometimes it's `%core::fmt::Void`,
+    // sometimes is `%core::fmt::Void.12` and on occasion it's even `%SomeRandomType`
+    //
+    // To cope with this weird fact the following piece of code will try to find the right LLVM
+    // type.
+    let all_maybe_void = indirects
+        .keys()
+        .filter_map(|sig| match (&sig.inputs[..], sig.output.as_ref()) {
+            ([Type::Pointer(receiver), Type::Pointer(formatter)], Some(output))
+                if **formatter == Type::Alias("core::fmt::Formatter")
+                    && **output == Type::Integer(1) =>
+            {
+                if let Type::Alias(receiver) = **receiver {
+                    Some(receiver)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let one_true_void = if all_maybe_void.contains(&"fmt::Void") {
+        Some("fmt::Void")
+    } else {
+        all_maybe_void
+            .iter()
+            .filter_map(|maybe_void| {
+                // this could be `core::fmt::Void` or `core::fmt::Void.12`
+                if maybe_void.starts_with("core::fmt::Void") {
+                    Some(*maybe_void)
+                } else {
+                    None
+                }
+            })
+            .next()
+            .or_else(|| {
+                if all_maybe_void.len() == 1 {
+                    // we got a random type!
+                    Some(all_maybe_void[0])
+                } else {
+                    None
+                }
+            })
+    };
+
+    // the type-based callees of each `fn` pointer type
+    let mut fn_ptrs = vec![];
+    for (mut sig, indirect) in indirects {
+        if !indirect.called {
+            continue;
+        }
+
+        let callees = if let Some(one_true_void) = one_true_void {
+            match (&sig.inputs[..], sig.output.as_ref()) {
+                // special case: this is `ArgumentV1.formatter` a pseudo trait object
+                ([Type::Pointer(void), Type::Pointer(fmt)], Some(output))
+                    if **void == Type::Alias(one_true_void)
+                        && **fmt == Type::Alias("core::fmt::Formatter")
+                        && **output == Type::Integer(1) =>
+                {
+                    if fmts.is_empty() {
+                        error!(
+                            diagnostics,
+                            Rule::Internal,
+                            "BUG? no callees for `{}`",
+                            sig.to_string()
+                        );
+                    }
+
+                    // canonicalize the signature
+                    if one_true_void != "fmt::Void" {
+                        sig.inputs[0] = Type::Alias("fmt::Void");
+                    }
+
+                    fmts.clone()
+                }
+
+                _ => indirect.callees,
+            }
+        } else {
+            indirect.callees
+        };
+
+        fn_ptrs.push((sig, indirect.callers, callees));
+    }
+
+    // `fn` pointers and vtables are values that flow from the functions that take their address to
+    // the functions that make indirect calls. We only have address-taken information if the
+    // LLVM-IR refers to symbols in places other than direct calls
+    let has_refs = !globals.is_empty() || refs.values().any(|syms| !syms.is_empty());
+    // symbols in `static` variables (e.g. `static HANDLERS: [fn(); N]`) or written to memory at
+    // runtime can reach any indirect call
+    let escaped = globals
+        .values()
+        .flatten()
+        .chain(&stores)
+        .cloned()
+        .collect::<HashSet<_>>();
+    // functions passed to other functions can be stored by the callee (e.g. `register(handler)`)
+    // and then reach any indirect call too
+    let mut escaped_fns = escaped
+        .iter()
+        .chain(&args)
+        .filter_map(|sym| aliases.get(sym).map(|canonical| indices[*canonical]))
+        .collect::<HashSet<_>>();
+
+    // a mutable table of `fn` pointers (e.g. `static mut HANDLERS: [fn(); N]`) that the program
+    // accesses can be filled at runtime (e.g. `HANDLERS[i] = f` in a `register(f)` function) with
+    // values we can't track, not just with the entries of its initializer; in that case any
+    // function whose address is taken in the LLVM-IR can reach any indirect call
+    let runtime_table = globals.iter().any(|(name, syms)| {
+        mutable_globals.contains(name)
+            && syms.iter().any(|sym| aliases.contains_key(sym))
+            && refs.values().flatten().any(|sym| sym == name)
+    });
+    if runtime_table {
+        escaped_fns.extend(
+            refs.values()
+                .flatten()
+                .filter_map(|sym| aliases.get(sym).map(|canonical| indices[*canonical])),
+        );
+    }
+
+    // functions whose address appears in the ELF, outside the machine code of the functions that
+    // are in the LLVM-IR (e.g. in C code or in the vector table), can also reach any indirect call
+    let functions = symbols
+        .defined
+        .iter()
+        .map(|(address, sym)| {
+            let canonical_name = aliases[&sym.names()[0]];
+
+            (*address, (sym.size(), defined.contains(canonical_name)))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let elf = ElfFile::new(input.elf).map_err(failure::err_msg)?;
+    escaped_fns.extend(
+        address_taken::scan(&elf, &functions, target_.is_thumb())
+            .iter()
+            .filter_map(|address| addr2name.get(address).map(|name| indices[*name])),
+    );
+
+    // the call graph plus an edge from each indirect call to every function of a matching type;
+    // this over-approximates how `fn` pointers and vtables flow between functions
+    let mut flow = g.map(|_, _| (), |_, _| ());
+    for (_, callers, callees) in &fn_ptrs {
+        for caller in callers.keys() {
+            for callee in callees {
+                flow.add_edge(*caller, *callee, ());
+            }
+        }
+    }
+    for dynamic in dynamics.values() {
+        for caller in dynamic.callers.keys() {
+            for callee in &dynamic.callees {
+                flow.add_edge(*caller, *callee, ());
+            }
+        }
+    }
+
+    // add fictitious nodes for indirect function calls
+    let mut reaching_fns = HashMap::new(); // caller -> functions whose address reaches it
+    for (sig, callers, callees) in fn_ptrs {
+        let mut name = sig.to_string();
+        // append '*' to denote that this is a function pointer
+        name.push('*');
+
+        // callees -> callers that can reach them
+        let mut groups = BTreeMap::<Vec<NodeIndex>, Vec<(NodeIndex, Edge)>>::new();
+        let annotated = annotated_calls.signature(&name);
+        if let Some(annotated) = annotated {
+            assumptions.push(format!(
+                "calls through `{}` can only reach {}",
+                name,
+                annotated
+                    .iter()
+                    .map(|callee| format!("`{}`", g[*callee].demangled))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+
+            let mut annotated = annotated.iter().cloned().collect::<Vec<_>>();
+            annotated.sort();
+            groups.insert(annotated, callers.into_iter().collect());
+        } else if !has_refs {
+            // no address-taken information; any function with a matching type could be called
+            let mut callees = callees.into_iter().collect::<Vec<_>>();
+            callees.sort();
+            groups.insert(callees, callers.into_iter().collect());
+        } else {
+            for (caller, edge) in callers {
+                let reaching = reaching_fns.entry(caller).or_insert_with(|| {
+                    reaching_refs(&flow, caller, &refs, |sym| aliases.contains_key(&sym))
+                        .into_iter()
+                        .map(|sym| indices[aliases[&sym]])
+                        .collect::<HashSet<_>>()
+                });
+
+                let mut reachable = callees
+                    .iter()
+                    .filter(|callee| {
+                        // the machine code of other targets computes addresses in ways that
+                        // `address_taken::scan` can't see so we can't tell whether functions that
+                        // are not in the LLVM-IR have their address taken
+                        (!target_.is_thumb() && !defined.contains(&*g[**callee].name))
+                            || escaped_fns.contains(callee)
+                            || reaching.contains(callee)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if reachable.is_empty() && !callees.is_empty() {
+                    warning!(
+                        diagnostics,
+                        Rule::IndirectCall,
+                        function = g[caller].name,
+                        "no function of type `{}` has its address taken where `{}` can see it; \
+                         assuming it can call all of them",
+                        name,
+                        g[caller].demangled
+                    );
+
+                    reachable = callees.iter().cloned().collect();
+                }
+
+                reachable.sort();
+                groups.entry(reachable).or_default().push((caller, edge));
+            }
+        }
+
+        for (callees, callers) in groups {
+            let call = g.add_node(Node(name.clone(), Some(0), true));
+
+            for (caller, edge) in callers {
+                g.add_edge(caller, call, edge);
+            }
+
+            if annotated.is_some() {
+                // the user told us that there are no other callees
+            } else if has_untyped_symbols {
+                // add an edge between this and a potential extern / untyped symbol
+                let extern_sym = g.add_node(Node("?", None, false));
+                g[extern_sym].unknown = Some(Unknown::IndirectCall);
+                g.add_edge(call, extern_sym, Edge::default());
+            } else if callees.is_empty() {
+                error!(
+                    diagnostics,
+                    Rule::Internal,
+                    "BUG? no callees for `{}`",
+                    name
+                );
+            }
+
+            for callee in callees {
+                g.add_edge(call, callee, Edge::default());
+            }
+        }
+    }
+
+    // vtables are the global variables that contain trait methods; vtable -> methods
+    let methods = dynamics
+        .values()
+        .flat_map(|dynamic| dynamic.callees.iter().cloned())
+        .collect::<HashSet<_>>();
+    let vtables = globals
+        .iter()
+        .filter_map(|(name, syms)| {
+            let vtable = syms
+                .iter()
+                .filter_map(|sym| aliases.get(sym).map(|canonical| indices[*canonical]))
+                .filter(|idx| methods.contains(idx))
+                .collect::<HashSet<_>>();
+
+            if vtable.is_empty() {
+                None
+            } else {
+                Some((*name, vtable))
+            }
+        })
+        .collect::<HashMap<_, _>>();
+    // like functions, vtables passed to other functions can be stored by the callee (e.g.
+    // `log::set_logger(&LOGGER)`) and then reach any dynamic dispatch
+    let static_vtables = escaped
+        .iter()
+        .chain(&args)
+        .filter(|sym| vtables.contains_key(*sym))
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    // add fictitious nodes for dynamic dispatch
+    let mut reaching_vtables = HashMap::new(); // caller -> vtables that reach it
+    for (sig, dynamic) in dynamics {
+        if !dynamic.called {
+            continue;
+        }
+
+        let name = sig.to_string();
+
+        // callees -> callers that can reach them
+        let mut groups = BTreeMap::<Vec<NodeIndex>, Vec<(NodeIndex, Edge)>>::new();
+        if let Some(annotated) = annotated_calls.signature(&name) {
+            assumptions.push(format!(
+                "calls through `{}` can only reach {}",
+                name,
+                annotated
+                    .iter()
+                    .map(|callee| format!("`{}`", g[*callee].demangled))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+
+            let mut callees = annotated.iter().cloned().collect::<Vec<_>>();
+            callees.sort();
+            groups.insert(callees, dynamic.callers.into_iter().collect());
+        } else if vtables.is_empty() {
+            // no vtable information; any method with a matching signature could be called
+            let mut callees = dynamic.callees.into_iter().collect::<Vec<_>>();
+            callees.sort();
+            groups.insert(callees, dynamic.callers.into_iter().collect());
+        } else {
+            for (caller, edge) in dynamic.callers {
+                let reaching = reaching_vtables.entry(caller).or_insert_with(|| {
+                    reaching_refs(&flow, caller, &refs, |sym| vtables.contains_key(sym))
+                });
+
+                let mut callees = dynamic
+                    .callees
+                    .iter()
+                    .filter(|callee| {
+                        reaching
+                            .iter()
+                            .chain(&static_vtables)
+                            .any(|vtable| vtables[vtable].contains(callee))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if callees.is_empty() && !dynamic.callees.is_empty() {
+                    warning!(
+                        diagnostics,
+                        Rule::IndirectCall,
+                        function = g[caller].name,
+                        "no vtable that reaches `{}` contains a method of type `{}`; assuming it \
+                         can call all of them",
+                        g[caller].demangled,
+                        name
+                    );
+
+                    callees = dynamic.callees.iter().cloned().collect();
+                }
+
+                callees.sort();
+                groups.entry(callees).or_default().push((caller, edge));
+            }
+        }
+
+        for (callees, callers) in groups {
+            if callees.is_empty() {
+                error!(
+                    diagnostics,
+                    Rule::Internal,
+                    "BUG? no callees for `{}`",
+                    name
+                );
+            }
+
+            let call = g.add_node(Node(name.clone(), Some(0), true));
+            for (caller, edge) in callers {
+                g.add_edge(caller, call, edge);
+            }
+
+            for callee in callees {
+                g.add_edge(call, callee, Edge::default());
+            }
+        }
+    }
+
+    let vectors = if target_.is_thumb() {
+        let elf = ElfFile::new(input.elf).map_err(failure::err_msg)?;
+        preemption::vectors(&elf, &addr2name, &mut diagnostics)
+    } else {
+        vec![]
+    };
+
+    // filter the call graph
+    let mut start_node = None;
+    if let Some(start) = input.start {
+        let start = indices.get(start).cloned().or_else(|| {
+            let start_ = start.to_owned() + "::h";
+            let hits = indices
+                .keys()
+                .filter(|key| {
+                    rustc_demangle::demangle(key)
+                        .to_string()
+                        .starts_with(&start_)
+                })
+                .collect::<Vec<_>>();
+
+            if hits.len() > 1 {
+                error!(
+                    diagnostics,
+                    Rule::Input,
+                    "multiple matches for `{}`: {:?}",
+                    start,
+                    hits
+                );
+                None
+            } else {
+                hits.first().map(|key| indices[*key])
+            }
+        });
+
+        if let Some(start) = start {
+            // create a new graph that only contains nodes reachable from `start`
+            let mut g2 = DiGraph::<Node, Edge>::new();
+
+            // maps `g`'s `NodeIndex`-es to `g2`'s `NodeIndex`-es
+            let mut one2two = BTreeMap::new();
+
+            let mut dfs = Dfs::new(&g, start);
+            while let Some(caller1) = dfs.next(&g) {
+                let caller2 = if let Some(i2) = one2two.get(&caller1) {
+                    *i2
+                } else {
+                    let i2 = g2.add_node(g[caller1].clone());
+                    one2two.insert(caller1, i2);
+                    i2
+                };
+
+                for edge in g.edges(caller1) {
+                    let callee1 = edge.target();
+                    let callee2 = if let Some(i2) = one2two.get(&callee1) {
+                        *i2
+                    } else {
+                        let i2 = g2.add_node(g[callee1].clone());
+                        one2two.insert(callee1, i2);
+                        i2
+                    };
+
+                    g2.add_edge(caller2, callee2, *edge.weight());
+                }
+            }
+
+            // replace the old graph
+            g = g2;
+            start_node = Some(one2two[&start]);
+
+            // invalidate `indices` to prevent misuse
+            indices.clear();
+        } else {
+            error!(
+                diagnostics,
+                Rule::Input,
+                "start point not found; the graph will not be filtered"
+            )
+        }
+    }
+
+    // maximum number of frames of these functions that can be on the stack at the same time
+    let recursions = annotations
+        .recursion
+        .iter()
+        .map(|recursion| {
+            let functions = recursion
+                .functions
+                .iter()
+                .filter_map(|function| annotations::resolve(&g, function, &mut diagnostics))
+                .collect::<Vec<_>>();
+
+            (functions, recursion.depth)
+        })
+        .collect::<Vec<_>>();
+
+    let mut cycles = vec![];
+    let mut cycle_locals = vec![];
+    if !has_stack_usage_info {
+        error!(
+            diagnostics,
+            Rule::Input,
+            "The graph has zero stack usage information; skipping max stack usage analysis"
+        );
+    } else if algo::is_cyclic_directed(&g) {
+        let sccs = algo::kosaraju_scc(&g);
+
+        // iterate over SCCs (Strongly Connected Components) in reverse topological order
+        for scc in &sccs {
+            let first = scc[0];
+
+            let is_a_cycle = scc.len() > 1
+                || g.neighbors_directed(first, Direction::Outgoing)
+                    .any(|n| n == first);
+
+            if is_a_cycle {
+                cycles.push(scc.clone());
+
+                // use the tightest bound the recursion annotations give us, if any
+                let mut bounded: Option<(Max, &[NodeIndex], u64)> = None;
+                for (functions, depth) in &recursions {
+                    if !functions.iter().any(|function| scc.contains(function)) {
+                        continue;
+                    }
+
+                    if let Some(bound) = bounded_scc_local(&g, scc, functions, *depth) {
+                        let is_tighter = bounded
+                            .map(|(current, ..)| match (current, bound) {
+                                (Max::LowerBound(_), Max::Exact(_)) => true,
+                                (Max::Exact(_), Max::LowerBound(_)) => false,
+                                _ => bound.value() < current.value(),
+                            })
+                            .unwrap_or(true);
+
+                        if is_tighter {
+                            bounded = Some((bound, functions, *depth));
+                        }
+                    } else {
+                        warning!(
+                            diagnostics,
+                            Rule::Annotations,
+                            "annotations: not all the cycles of SCC{} go through {}; ignoring \
+                             its recursion depth",
+                            cycles.len() - 1,
+                            functions
+                                .iter()
+                                .map(|function| format!("`{}`", g[*function].demangled))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+                }
+
+                let scc_local = if let Some((bound, functions, depth)) = bounded {
+                    assumptions.push(format!(
+                        "at most {} frames of {} can be on the stack at the same time",
+                        depth,
+                        functions
+                            .iter()
+                            .map(|function| format!("`{}`", g[*function].demangled))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+
+                    bound
+                } else {
+                    warning!(
+                        diagnostics,
+                        Rule::Recursion,
+                        function = g[first].name,
+                        "SCC{} ({}) is recursive and has no `[[recursion]]` annotation; the max \
+                         stack usage of the functions that call into it is a lower bound",
+                        cycles.len() - 1,
+                        scc.iter()
+                            .map(|function| format!("`{}`", g[*function].demangled))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+
+                    scc_local(&g, scc)
+                };
+                cycle_locals.push(scc_local);
+
+                let neighbors_max = max_callee(
+                    &g,
+                    scc.iter().flat_map(|inode| {
+                        g.neighbors_directed(*inode, Direction::Outgoing)
+                            // we only care about the neighbors of the SCC
+                            .filter(|neighbor| !scc.contains(neighbor))
+                    }),
+                );
+
+                for inode in scc {
+                    let node = &mut g[*inode];
+                    if let Some((callee, max)) = neighbors_max {
+                        node.max = Some(max + scc_local);
+                        node.max_callee = Some(callee);
+                    } else {
+                        node.max = Some(scc_local);
+                    }
+                }
+            } else {
+                let inode = first;
+
+                let neighbors_max =
+                    max_callee(&g, g.neighbors_directed(inode, Direction::Outgoing));
+
+                let node = &mut g[inode];
+                if let Some((callee, max)) = neighbors_max {
+                    node.max = Some(max + node.local);
+                    node.max_callee = Some(callee);
+                } else {
+                    node.max = Some(node.local.into());
+                }
+            }
+        }
+    } else {
+        // compute max stack usage
+        let mut topo = Topo::new(Reversed(&g));
+        while let Some(node) = topo.next(Reversed(&g)) {
+            debug_assert!(g[node].max.is_none());
+
+            let neighbors_max = max_callee(&g, g.neighbors_directed(node, Direction::Outgoing));
+
+            if let Some((callee, max)) = neighbors_max {
+                g[node].max = Some(max + g[node].local);
+                g[node].max_callee = Some(callee);
+            } else {
+                g[node].max = Some(g[node].local.into());
+            }
+        }
+    }
+
+    for idx in recursions.iter().flat_map(|(functions, _)| functions) {
+        if !cycles.iter().any(|cycle| cycle.contains(idx)) {
+            warning!(
+                diagnostics,
+                Rule::Annotations,
+                function = g[*idx].name,
+                "annotations: `{}` is not part of a cycle; ignoring its recursion depth",
+                g[*idx].demangled
+            );
+        }
+    }
+
+    // here we try to shorten the name of the symbol if it doesn't result in ambiguity
+    for node in g.node_weights_mut() {
+        if let Some(dehashed) = dehash(&node.demangled) {
+            if ambiguous[dehashed] == 1 {
+                node.demangled = dehashed.to_owned();
+            }
+        }
+    }
+
+    Ok(CallGraph {
+        graph: g,
+        cycles,
+        cycle_locals,
+        start: start_node,
+        vectors,
+        diagnostics,
+        assumptions,
+    })
+}
+
+/// A function in the call graph
+#[derive(Clone, Debug)]
+pub struct Node<'a> {
+    /// Symbol name; fictitious nodes use the signature of the indirect call here
+    pub name: Cow<'a, str>,
+    /// The demangled name, without the hash if that doesn't result in ambiguity
+    pub demangled: String,
+    /// Stack usage of this function
+    pub local: Local,
+    /// Stack usage of this function plus the stack usage of the functions it may call; `None` if
+    /// the analysis was skipped
+    pub max: Option<Max>,
+    /// The callee that realizes `max`; for nodes in a cycle this is a neighbor of the cycle
+    pub max_callee: Option<NodeIndex>,
+    /// Fictitious nodes (indirect function calls) are drawn with dashed borders
+    pub dashed: bool,
+    /// Where the function is defined, if there's debug information about it
+    pub location: Option<Location<'a>>,
+    /// The archive the stack usage information comes from (e.g. `libcore-0123456789abcdef.rlib`);
+    /// `None` if it comes from the program's object file or from somewhere else
+    pub origin: Option<&'a str>,
+    /// Why `local` is `Local::Unknown`; only meaningful in that case
+    pub unknown: Option<Unknown>,
+}
+
+#[allow(non_snake_case)]
+fn Node<'a, S>(name: S, stack: Option<u64>, dashed: bool) -> Node<'a>
+where
+    S: Into<Cow<'a, str>>,
+{
+    let name = name.into();
+    let demangled = rustc_demangle::demangle(&name).to_string();
+
+    Node {
+        name,
+        demangled,
+        local: stack.map(Local::Exact).unwrap_or(Local::Unknown),
+        max: None,
+        max_callee: None,
+        dashed,
+        location: None,
+        origin: None,
+        unknown: None,
+    }
+}
+
+/// A call from one function to another
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Edge<'a> {
+    /// Where the caller makes the call, if there's debug information about it
+    pub location: Option<Location<'a>>,
+}
+
+impl<'a> Edge<'a> {
+    // a call made at the `DILocation` `loc`
+    fn at(dbg: &DebugInfos<'a>, loc: Option<u32>) -> Self {
+        Edge {
+            location: loc.and_then(|id| dbg.call_site(id)),
+        }
+    }
+}
+
+/// Local stack usage
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Local {
+    Exact(u64),
+    Unknown,
+}
+
+impl fmt::Display for Local {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Local::Exact(n) => write!(f, "{}", n),
+            Local::Unknown => f.write_str("?"),
+        }
+    }
+}
+
+impl From<Local> for Max {
+    fn from(local: Local) -> Max {
+        match local {
+            Local::Exact(n) => Max::Exact(n),
+            Local::Unknown => Max::LowerBound(0),
+        }
+    }
+}
+
+/// Why the stack usage of a function is unknown
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unknown {
+    /// There's no stack usage information about the function in the object files (e.g. it was
+    /// written in C or assembly)
+    NoInfo,
+    /// The function is not defined in the program
+    Undefined,
+    /// The machine code of the function modifies the stack pointer in a way our analysis can't
+    /// follow (e.g. `alloca` with a dynamic size)
+    Analysis,
+    /// The callee of an indirect function call whose possible callees we couldn't narrow down
+    IndirectCall,
+}
+
+impl fmt::Display for Unknown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Unknown::NoInfo => "no stack usage information in the object files",
+            Unknown::Undefined => "not defined in the program",
+            Unknown::Analysis => "the machine code analysis couldn't compute it",
+            Unknown::IndirectCall => "unknown callee of an indirect function call",
+        })
+    }
+}
+
+/// Maximum stack usage
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Max {
+    Exact(u64),
+    LowerBound(u64),
+}
+
+impl ops::Add<Local> for Max {
+    type Output = Max;
+
+    fn add(self, rhs: Local) -> Max {
+        match (self, rhs) {
+            (Max::Exact(lhs), Local::Exact(rhs)) => Max::Exact(lhs + rhs),
+            (Max::Exact(lhs), Local::Unknown) => Max::LowerBound(lhs),
+            (Max::LowerBound(lhs), Local::Exact(rhs)) => Max::LowerBound(lhs + rhs),
+            (Max::LowerBound(lhs), Local::Unknown) => Max::LowerBound(lhs),
+        }
+    }
+}
+
+impl ops::Add<Max> for Max {
+    type Output = Max;
+
+    fn add(self, rhs: Max) -> Max {
+        match (self, rhs) {
+            (Max::Exact(lhs), Max::Exact(rhs)) => Max::Exact(lhs + rhs),
+            (Max::Exact(lhs), Max::LowerBound(rhs)) => Max::LowerBound(lhs + rhs),
+            (Max::LowerBound(lhs), Max::Exact(rhs)) => Max::LowerBound(lhs + rhs),
+            (Max::LowerBound(lhs), Max::LowerBound(rhs)) => Max::LowerBound(lhs + rhs),
+        }
+    }
+}
+
+impl ops::Mul<u64> for Max {
+    type Output = Max;
+
+    fn mul(self, rhs: u64) -> Max {
+        match self {
+            Max::Exact(lhs) => Max::Exact(lhs * rhs),
+            Max::LowerBound(lhs) => Max::LowerBound(lhs * rhs),
+        }
+    }
+}
+
+fn max_of(mut iter: impl Iterator<Item = Max>) -> Option<Max> {
+    iter.next().map(|first| iter.fold(first, max))
+}
+
+// like `max_of` but also returns the callee that has the largest max stack usage
+//
+// ties are not broken by the order of the edges, which can change between builds; instead exact
+// results win over lower bounds and then the function that comes first by name wins
+fn max_callee(
+    g: &Graph<Node, Edge>,
+    callees: impl Iterator<Item = NodeIndex>,
+) -> Option<(NodeIndex, Max)> {
+    let mut callees = callees.map(|callee| (callee, g[callee].max.expect("UNREACHABLE")));
+    let key = |idx: NodeIndex, max: Max| {
+        (
+            cmp::Reverse(max.value()),
+            matches!(max, Max::LowerBound(_)),
+            &g[idx].demangled,
+            &g[idx].name,
+        )
+    };
+
+    callees.next().map(|first| {
+        callees.fold(first, |(lhs_idx, lhs), (rhs_idx, rhs)| {
+            let idx = if key(rhs_idx, rhs) < key(lhs_idx, lhs) {
+                rhs_idx
+            } else {
+                lhs_idx
+            };
+
+            (idx, max(lhs, rhs))
+        })
+    })
+}
+
+// the stack usage of the SCC itself, excluding the stack usage of its neighbors
+fn scc_local(g: &Graph<Node, Edge>, scc: &[NodeIndex]) -> Max {
+    let scc_local = max_of(scc.iter().map(|node| g[*node].local.into())).expect("UNREACHABLE");
+
+    // the cumulative stack usage is only exact when all nodes do *not* use the stack
+    match scc_local {
+        Max::Exact(n) if n != 0 => Max::LowerBound(n),
+        _ => scc_local,
+    }
+}
+
+// upper bound of the stack usage of the SCC itself when at most `depth` frames of `functions` can be
+// on the stack at the same time; `None` if some cycle of the SCC doesn't go through `functions`
+//
+// While in the SCC, the stack looks like this: a path through the rest of the SCC, a frame of one of
+// `functions`, another path through the rest of the SCC, another frame of one of `functions`, and
+// so on. The rest of the SCC has no cycles so none of those paths can be heavier than its heaviest
+// path.
+fn bounded_scc_local(
+    g: &Graph<Node, Edge>,
+    scc: &[NodeIndex],
+    functions: &[NodeIndex],
+    depth: u64,
+) -> Option<Max> {
+    let frame = max_of(
+        functions
+            .iter()
+            .filter(|function| scc.contains(function))
+            .map(|function| g[*function].local.into()),
+    )?;
+
+    // the rest of the SCC
+    let rest = g.filter_map(
+        |idx, node| {
+            if scc.contains(&idx) && !functions.contains(&idx) {
+                Some(node.local)
+            } else {
+                None
+            }
+        },
+        |_, _| Some(()),
+    );
+
+    // heaviest path that starts at each node
+    let mut heaviest = HashMap::new();
+    for idx in algo::toposort(&rest, None).ok()?.into_iter().rev() {
+        let callees = max_of(
+            rest.neighbors_directed(idx, Direction::Outgoing)
+                .map(|callee| heaviest[&callee]),
+        );
+
+        heaviest.insert(idx, callees.unwrap_or(Max::Exact(0)) + rest[idx]);
+    }
+    let path = max_of(heaviest.values().cloned()).unwrap_or(Max::Exact(0));
+
+    Some(frame * depth + path * (depth + 1))
+}
+
+// `relevant` symbols (e.g. vtables) that can reach the indirect calls made by `caller`: those
+// referred to by the functions that (transitively) call it and by all the functions these call. A
+// symbol taken by a callee of a caller can be returned to the caller and then passed down to
+// `caller`
+fn reaching_refs<'a>(
+    flow: &Graph<(), ()>,
+    caller: NodeIndex,
+    refs: &HashMap<NodeIndex, Vec<&'a str>>,
+    relevant: impl Fn(&str) -> bool,
+) -> HashSet<&'a str> {
+    let mut reaching = HashSet::new();
+    let mut visit = |idx| {
+        if let Some(syms) = refs.get(&idx) {
+            reaching.extend(syms.iter().filter(|sym| relevant(sym)));
+        }
+    };
+
+    let mut callers = Dfs::new(Reversed(flow), caller);
+    let mut callees = Dfs::empty(flow);
+    while let Some(idx) = callers.next(Reversed(flow)) {
+        callees.stack.push(idx);
+    }
+
+    while let Some(idx) = callees.next(flow) {
+        visit(idx);
+    }
+
+    reaching
+}
+
+fn max(lhs: Max, rhs: Max) -> Max {
+    match (lhs, rhs) {
+        (Max::Exact(lhs), Max::Exact(rhs)) => Max::Exact(cmp::max(lhs, rhs)),
+        (Max::Exact(lhs), Max::LowerBound(rhs)) => Max::LowerBound(cmp::max(lhs, rhs)),
+        (Max::LowerBound(lhs), Max::Exact(rhs)) => Max::LowerBound(cmp::max(lhs, rhs)),
+        (Max::LowerBound(lhs), Max::LowerBound(rhs)) => Max::LowerBound(cmp::max(lhs, rhs)),
+    }
+}
+
+impl Max {
+    /// The number of bytes, regardless of whether this is exact or a lower bound
+    pub fn value(&self) -> u64 {
+        match *self {
+            Max::Exact(n) | Max::LowerBound(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Max {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Max::Exact(n) => write!(f, "= {}", n),
+            Max::LowerBound(n) => write!(f, ">= {}", n),
+        }
+    }
+}
+
+// used to track indirect function calls (`fn` pointers)
+#[derive(Default)]
+struct Indirect<'a> {
+    called: bool,
+    // caller -> first call site
+    callers: HashMap<NodeIndex, Edge<'a>>,
+    callees: HashSet<NodeIndex>,
+}
+
+// used to track dynamic dispatch (trait objects)
+#[derive(Debug, Default)]
+struct Dynamic<'a> {
+    called: bool,
+    // caller -> first call site
+    callers: HashMap<NodeIndex, Edge<'a>>,
+    callees: HashSet<NodeIndex>,
+}
+
+// looks up a node by its symbol name or by its demangled name (with or without hash)
+pub(crate) fn find(g: &Graph<Node, Edge>, name: &str) -> Vec<NodeIndex> {
+    g.node_indices()
+        .filter(|idx| {
+            let node = &g[*idx];
+
+            matches(&node.name, &node.demangled, name)
+        })
+        .collect()
+}
+
+// whether `needle` refers to the symbol `name`
+pub(crate) fn matches(name: &str, demangled: &str, needle: &str) -> bool {
+    name == needle
+        || demangled == needle
+        || dehash(&rustc_demangle::demangle(name).to_string()) == Some(needle)
+}
+
+// removes hashes like `::hfc5adc5d79855638`, if present
+fn dehash(demangled: &str) -> Option<&str> {
+    const HASH_LENGTH: usize = 19;
+
+    let len = demangled.len();
+    if len > HASH_LENGTH {
+        if demangled
+            .get(len - HASH_LENGTH..)
+            .map(|hash| hash.starts_with("::h"))
+            .unwrap_or(false)
+        {
+            Some(&demangled[..len - HASH_LENGTH])
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Target {
+    Aarch64,
+    Other,
+    Riscv32,
+    Riscv64,
+    Thumbv6m,
+    Thumbv7m,
+    Thumbv8mBase,
+    Thumbv8mMain,
+}
+
+impl Target {
+    fn from_triple(target: &str) -> Self {
+        match target {
+            "thumbv6m-none-eabi" => Target::Thumbv6m,
+            "thumbv7m-none-eabi" | "thumbv7em-none-eabi" | "thumbv7em-none-eabihf" => {
+                Target::Thumbv7m
+            }
+            "thumbv8m.base-none-eabi" => Target::Thumbv8mBase,
+            "thumbv8m.main-none-eabi" | "thumbv8m.main-none-eabihf" => Target::Thumbv8mMain,
+            _ if target.starts_with("aarch64") => Target::Aarch64,
+            _ if target.starts_with("riscv32") => Target::Riscv32,
+            _ if target.starts_with("riscv64") => Target::Riscv64,
+            _ => Target::Other,
+        }
+    }
+
+    fn is_thumb(&self) -> bool {
+        match *self {
+            Target::Thumbv6m | Target::Thumbv7m | Target::Thumbv8mBase | Target::Thumbv8mMain => {
+                true
+            }
+            Target::Aarch64 | Target::Other | Target::Riscv32 | Target::Riscv64 => false,
+        }
+    }
+
+    // whether we can analyze the machine code of this target
+    fn has_decoder(&self) -> bool {
+        *self != Target::Other
+    }
+}
+
+// analyzes the machine code `text` of the subroutine that starts at `address` (see
+// `thumb::analyze`); `tags` are only used on Thumb targets
+fn decode(
+    target_: Target,
+    text: &[u8],
+    address: u64,
+    tags: &[(u32, Tag)],
+) -> (Vec<i64>, Vec<i64>, bool, bool, Option<u64>) {
+    match target_ {
+        Target::Aarch64 => aarch64::analyze(text),
+
+        Target::Riscv32 | Target::Riscv64 => {
+            riscv::analyze(text, address, target_ == Target::Riscv64)
+        }
+
+        Target::Other => unreachable!(),
+
+        Target::Thumbv6m | Target::Thumbv7m | Target::Thumbv8mBase | Target::Thumbv8mMain => {
+            let arch = match target_ {
+                Target::Thumbv6m => Arch::V6M,
+                Target::Thumbv7m => Arch::V7M,
+                Target::Thumbv8mBase => Arch::V8MBase,
+                _ => Arch::V8MMain,
+            };
+
+            let (bls, bs, indirect, modifies_sp, our_stack) =
+                thumb::analyze(text, address as u32, arch, tags);
+
+            (
+                bls.into_iter().map(i64::from).collect(),
+                bs.into_iter().map(i64::from).collect(),
+                indirect,
+                modifies_sp,
+                our_stack,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        env,
+        fs::{self, File},
+    };
+
+    use ar::{Builder, Header};
+    use petgraph::graph::DiGraph;
+
+    use super::{bounded_scc_local, max_callee, reaching_refs, Edge, Max, Node};
+
+    #[test]
+    fn max_callee_ties() {
+        let mut g = DiGraph::new();
+        let c = g.add_node(Node("c", Some(8), false));
+        let b = g.add_node(Node("b", Some(8), false));
+        let a = g.add_node(Node("a", None, false));
+        g[c].max = Some(Max::Exact(8));
+        g[b].max = Some(Max::Exact(8));
+        g[a].max = Some(Max::LowerBound(8));
+
+        // the order of the callees doesn't matter
+        assert_eq!(
+            max_callee(&g, vec![c, b].into_iter()),
+            Some((b, Max::Exact(8)))
+        );
+        assert_eq!(
+            max_callee(&g, vec![b, c].into_iter()),
+            Some((b, Max::Exact(8)))
+        );
+
+        // exact results win over lower bounds of the same size
+        assert_eq!(
+            max_callee(&g, vec![a, c].into_iter()),
+            Some((c, Max::LowerBound(8)))
+        );
+
+        // but not over larger ones
+        g[a].max = Some(Max::LowerBound(16));
+        assert_eq!(
+            max_callee(&g, vec![c, a].into_iter()),
+            Some((a, Max::LowerBound(16)))
+        );
+    }
+
+    #[test]
+    fn recursion_depth() {
+        let mut g = DiGraph::new();
+        let a = g.add_node(Node("a", Some(8), false));
+        let b = g.add_node(Node("b", Some(16), false));
+        let c = g.add_node(Node("c", Some(4), false));
+        g.add_edge(a, b, Edge::default());
+        g.add_edge(b, c, Edge::default());
+        g.add_edge(c, a, Edge::default());
+        g.add_edge(b, a, Edge::default());
+        let scc = [a, b, c];
+
+        // 3 frames of `a` separated by `b -> c` paths
+        assert_eq!(
+            bounded_scc_local(&g, &scc, &[a], 3),
+            Some(Max::Exact(8 * 3 + 20 * 4))
+        );
+
+        // 3 frames of `b` separated by `c -> a` paths
+        assert_eq!(
+            bounded_scc_local(&g, &scc, &[b], 3),
+            Some(Max::Exact(16 * 3 + 12 * 4))
+        );
+
+        // 3 frames of the whole cycle
+        assert_eq!(
+            bounded_scc_local(&g, &scc, &[a, b, c], 3),
+            Some(Max::Exact(16 * 3))
+        );
+
+        // `a -> b -> a` doesn't go through `c`
+        assert_eq!(bounded_scc_local(&g, &scc, &[c], 3), None);
+
+        // unknown stack usage
+        let d = g.add_node(Node("d", None, false));
+        g.add_edge(c, d, Edge::default());
+        g.add_edge(d, a, Edge::default());
+        assert_eq!(
+            bounded_scc_local(&g, &[a, b, c, d], &[a], 1),
+            Some(Max::LowerBound(8 + 20 * 2))
+        );
+    }
+
+    #[test]
+    fn ref_flow() {
+        // main -> make_a, main -> run -> (dyn call), run -> make_c; handler -> (dyn call)
+        let mut g = DiGraph::new();
+        let main = g.add_node(());
+        let make_a = g.add_node(());
+        let run = g.add_node(());
+        let make_c = g.add_node(());
+        let handler = g.add_node(());
+        g.add_edge(main, make_a, ());
+        g.add_edge(main, run, ());
+        g.add_edge(run, make_c, ());
+
+        let is_vtable = |sym: &str| sym.starts_with("vtable.");
+        let mut refs = HashMap::new();
+        refs.insert(make_a, vec!["vtable.a"]); // returned to `main`, then passed to `run`
+        refs.insert(make_c, vec!["vtable.c"]); // returned to `run`
+        refs.insert(handler, vec!["vtable.b", "HANDLER_STATE"]);
+
+        let set = |names: &[&'static str]| names.iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(
+            reaching_refs(&g, run, &refs, is_vtable),
+            set(&["vtable.a", "vtable.c"])
+        );
+        assert_eq!(
+            reaching_refs(&g, make_c, &refs, is_vtable),
+            set(&["vtable.a", "vtable.c"])
+        );
+        assert_eq!(
+            reaching_refs(&g, handler, &refs, is_vtable),
+            set(&["vtable.b"])
+        );
+    }
+
+    #[test]
+    fn archive_objects() {
+        let path = env::temp_dir().join(format!("libfoo-{}.a", std::process::id()));
+
+        let mut ar = Builder::new(File::create(&path).unwrap());
+        for (id, data) in &[
+            ("lib.rmeta", &b"rust"[..]),
+            ("foo.o", &b"\x7fELF\x01\x01\x01"[..]),
+            ("bar.o", &b"BC\xc0\xde"[..]), // LLVM bitcode
+        ] {
+            ar.append(
+                &Header::new(id.as_bytes().to_vec(), data.len() as u64),
+                *data,
+            )
+            .unwrap();
+        }
+        drop(ar);
+
+        let objects = super::archive_objects(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(objects.len(), 1);
+        assert_eq!(
+            objects[0].origin,
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        assert_eq!(objects[0].bytes, b"\x7fELF\x01\x01\x01");
+    }
+}
@@ -0,0 +1,135 @@
+use std::io;
+
+use petgraph::Direction;
+
+use crate::{CallGraph, Local, Max};
+
+/// Writes the call chain that realizes the maximum stack usage of the start point or, if there's
+/// no start point, of each root of the call graph (nodes that have no callers)
+pub fn path<W>(cg: &CallGraph, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+    let cycles = &cg.cycles;
+
+    let roots = if let Some(start) = cg.start {
+        vec![start]
+    } else {
+        g.node_indices()
+            .filter(|idx| {
+                g.neighbors_directed(*idx, Direction::Incoming)
+                    .all(|caller| caller == *idx)
+            })
+            .collect()
+    };
+
+    let mut is_first = true;
+    for root in &roots {
+        if is_first {
+            is_first = false;
+        } else {
+            writeln!(stdout)?;
+        }
+
+        let max = if let Some(max) = g[*root].max {
+            max
+        } else {
+            writeln!(
+                stdout,
+                "`{}`: max stack usage is unknown (the analysis was skipped)",
+                g[*root].demangled
+            )?;
+            continue;
+        };
+
+        write!(stdout, "`{}`: max {}", g[*root].demangled, max)?;
+        if let Some(location) = g[*root].location {
+            write!(stdout, " (defined at {})", location)?;
+        }
+        writeln!(stdout)?;
+        writeln!(stdout)?;
+        writeln!(
+            stdout,
+            "{:>5}  {:>8}  {:>8}  function",
+            "frame", "local", "total"
+        )?;
+
+        let mut total = Max::Exact(0);
+        let mut caller = None;
+        let mut current = Some(*root);
+        let mut frame = 0;
+        while let Some(idx) = current {
+            let node = &g[idx];
+
+            let cycle = cycles
+                .iter()
+                .enumerate()
+                .find(|(_, cycle)| cycle.contains(&idx));
+
+            let local = if let Some((i, _)) = cycle {
+                cg.cycle_locals[i]
+            } else {
+                node.local.into()
+            };
+
+            total = total + local;
+
+            write!(
+                stdout,
+                "{:>5}  {:>8}  {:>8}  {}",
+                frame,
+                bytes(local),
+                bytes(total),
+                node.demangled
+            )?;
+
+            if let Some((i, cycle)) = cycle {
+                write!(stdout, " (cycle SCC{}:", i)?;
+                for member in cycle {
+                    write!(stdout, " `{}`", g[*member].demangled)?;
+                }
+                write!(stdout, ")")?;
+            } else if node.dashed {
+                if node.name.ends_with('*') {
+                    write!(stdout, " (call via function pointer)")?;
+                } else {
+                    write!(stdout, " (dynamic dispatch)")?;
+                }
+            } else if node.local == Local::Unknown {
+                write!(stdout, " (unknown stack usage)")?;
+            }
+
+            let call_site = caller
+                .and_then(|caller| g.find_edge(caller, idx))
+                .and_then(|edge| g[edge].location);
+            if let Some(location) = call_site {
+                write!(stdout, " (called at {})", location)?;
+            }
+
+            writeln!(stdout)?;
+
+            caller = Some(idx);
+            current = node.max_callee;
+            frame += 1;
+        }
+    }
+
+    if !cg.assumptions.is_empty() {
+        writeln!(stdout)?;
+        writeln!(stdout, "assumptions:")?;
+
+        for assumption in &cg.assumptions {
+            writeln!(stdout, "- {}", assumption)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn bytes(max: Max) -> String {
+    match max {
+        Max::Exact(n) => n.to_string(),
+        Max::LowerBound(n) => format!(">={}", n),
+    }
+}
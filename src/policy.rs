@@ -0,0 +1,117 @@
+use failure::format_err;
+
+use crate::{Diagnostic, Level, Rule};
+
+/// What to do with the diagnostics of each `Rule`
+#[derive(Debug, Default, PartialEq)]
+pub struct Policy {
+    /// Diagnostics of these rules are errors that fail the run
+    pub deny: Vec<Rule>,
+    /// Diagnostics of these rules are dropped; this takes precedence over `deny`
+    pub allow: Vec<Rule>,
+}
+
+impl Policy {
+    /// Parses the values of the `--deny` and `--allow` flags: rule codes (`CS001`), rule IDs
+    /// (`no-stack-info`) or `warnings`, which stands for all the rules
+    pub fn parse<'a>(
+        deny: impl Iterator<Item = &'a str>,
+        allow: impl Iterator<Item = &'a str>,
+    ) -> Result<Self, failure::Error> {
+        Ok(Policy {
+            deny: rules(deny)?,
+            allow: rules(allow)?,
+        })
+    }
+
+    /// Whether the diagnostics of `rule` fail the run
+    pub fn is_denied(&self, rule: Rule) -> bool {
+        self.deny.contains(&rule) && !self.allow.contains(&rule)
+    }
+
+    /// Drops the allowed `diagnostics` and turns the denied ones into errors; returns whether any
+    /// diagnostic was denied
+    pub fn apply(&self, diagnostics: &mut Vec<Diagnostic>) -> bool {
+        diagnostics.retain(|diagnostic| !self.allow.contains(&diagnostic.rule));
+
+        let mut denied = false;
+        for diagnostic in diagnostics {
+            if self.is_denied(diagnostic.rule) {
+                diagnostic.level = Level::Error;
+                denied = true;
+            }
+        }
+
+        denied
+    }
+}
+
+fn rules<'a>(values: impl Iterator<Item = &'a str>) -> Result<Vec<Rule>, failure::Error> {
+    let mut rules = vec![];
+    for value in values {
+        if value == "warnings" {
+            rules.extend_from_slice(Rule::ALL);
+        } else {
+            rules.push(Rule::parse(value).ok_or_else(|| {
+                format_err!(
+                    "unknown diagnostic `{}`; expected a code like `CS001`, an ID like \
+                     `no-stack-info` or `warnings`",
+                    value
+                )
+            })?);
+        }
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use crate::{Diagnostic, Level, Rule};
+
+    #[test]
+    fn apply() {
+        let policy = Policy::parse(
+            vec!["CS001", "recursion"].into_iter(),
+            vec!["cs007", "indirect-call"].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            Policy {
+                deny: vec![Rule::NoStackInfo, Rule::Recursion],
+                allow: vec![Rule::Recursion, Rule::IndirectCall],
+            }
+        );
+        assert!(Policy::parse(vec!["CS999"].into_iter(), vec![].into_iter()).is_err());
+
+        let diagnostic = |rule| Diagnostic {
+            level: Level::Warning,
+            rule,
+            function: None,
+            message: String::new(),
+        };
+
+        let mut diagnostics = vec![
+            diagnostic(Rule::NoStackInfo),
+            diagnostic(Rule::Recursion),
+            diagnostic(Rule::IndirectCall),
+            diagnostic(Rule::TypeInfo),
+        ];
+        assert!(policy.apply(&mut diagnostics));
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| (d.rule, d.level))
+                .collect::<Vec<_>>(),
+            vec![
+                (Rule::NoStackInfo, Level::Error),
+                (Rule::TypeInfo, Level::Warning),
+            ]
+        );
+
+        let mut diagnostics = vec![diagnostic(Rule::TypeInfo)];
+        assert!(!policy.apply(&mut diagnostics));
+    }
+}
@@ -0,0 +1,607 @@
+//! Stack usage of the whole system when exception handlers preempt each other (Cortex-M only)
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    io,
+};
+
+use failure::format_err;
+use petgraph::graph::NodeIndex;
+use xmas_elf::{
+    sections::{SectionData, ShType},
+    symbol_table::Entry,
+    ElfFile,
+};
+
+use crate::{dehash, ir::Location, max, path::bytes, CallGraph, Diagnostic, Max, Rule};
+
+/// An entry of the vector table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vector<'a> {
+    /// Exception number: `1` is `Reset`, `2` is `NMI`, ..., `15` is `SysTick`; device specific
+    /// interrupts start at `16`
+    pub number: u16,
+    /// Symbol name of the handler
+    pub handler: &'a str,
+}
+
+impl Vector<'_> {
+    /// The name of the exception, as used by `cortex-m-rt`; interrupts are named `IRQ<n>`
+    pub fn exception(&self) -> Cow<'static, str> {
+        match self.number {
+            1 => "Reset".into(),
+            2 => "NMI".into(),
+            3 => "HardFault".into(),
+            4 => "MemoryManagement".into(),
+            5 => "BusFault".into(),
+            6 => "UsageFault".into(),
+            7 => "SecureFault".into(),
+            11 => "SVCall".into(),
+            12 => "DebugMonitor".into(),
+            14 => "PendSV".into(),
+            15 => "SysTick".into(),
+            n if n >= 16 => format!("IRQ{}", n - 16).into(),
+            n => format!("Reserved{}", n).into(),
+        }
+    }
+}
+
+/// Logical priorities of the exception handlers
+///
+/// Unlike NVIC priorities, a higher number means a higher priority; thread mode runs at priority
+/// `0`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Priorities<'a> {
+    pub handlers: Vec<(&'a str, u8)>,
+}
+
+impl<'a> Priorities<'a> {
+    /// Parses a list of `HANDLER=PRIORITY` values
+    pub fn parse(values: impl Iterator<Item = &'a str>) -> Result<Self, failure::Error> {
+        let mut priorities = Priorities::default();
+
+        for value in values {
+            let pos = value
+                .rfind('=')
+                .ok_or_else(|| format_err!("`{}` is not of the form HANDLER=PRIORITY", value))?;
+            let (handler, priority) = (&value[..pos], &value[pos + 1..]);
+
+            match priority.parse() {
+                Ok(priority) if priority != 0 => priorities.handlers.push((handler, priority)),
+                _ => {
+                    return Err(format_err!(
+                        "`{}` is not a valid priority (expected a number between 1 and 255)",
+                        value
+                    ));
+                }
+            }
+        }
+
+        Ok(priorities)
+    }
+}
+
+/// The nesting level at which a handler runs
+///
+/// Handlers at the same level can't preempt each other
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Configurable priority
+    Logical(u8),
+    /// No priority was given; the handler is assumed to be able to preempt all the other handlers
+    Unknown,
+    /// Fixed priority `-1`
+    HardFault,
+    /// Fixed priority `-2`
+    Nmi,
+}
+
+/// Worst case stack usage of the whole system
+#[derive(Debug)]
+pub struct System {
+    /// The `Reset` handler, which runs in thread mode
+    pub thread: NodeIndex,
+    /// The handlers that realize the worst case, one per nesting level, from lowest to highest
+    /// priority, and the max stack usage of their level
+    pub levels: Vec<(Priority, NodeIndex, Max)>,
+    /// Bytes the hardware pushes onto the stack on exception entry
+    pub frame: u64,
+    /// Thread mode plus all the nesting levels
+    pub max: Max,
+}
+
+/// Size of the exception frame the hardware stacks on exception entry
+///
+/// That's 8 words or, on targets with a FPU, 26 words (the FPU context is included), plus the word
+/// of padding the hardware may insert to keep the stack 8-byte aligned (STKALIGN)
+pub fn frame_size(target: &str) -> u64 {
+    let words = if target.ends_with("eabihf") { 26 } else { 8 };
+
+    (words + 1) * 4
+}
+
+// reads the vector table from the ELF file and maps its entries to symbol names
+pub(crate) fn vectors<'a>(
+    elf: &ElfFile<'a>,
+    addr2name: &BTreeMap<&u64, &'a str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Vector<'a>> {
+    let table = if let Some(table) = table(elf) {
+        table
+    } else {
+        return vec![];
+    };
+
+    let mut vectors = vec![];
+    // the first word is the initial value of the stack pointer
+    for (number, word) in table.chunks_exact(4).enumerate().skip(1) {
+        let address = u64::from(
+            u32::from(word[0])
+                | u32::from(word[1]) << 8
+                | u32::from(word[2]) << 16
+                | u32::from(word[3]) << 24,
+        );
+
+        if address == 0 {
+            // reserved entry
+            continue;
+        }
+
+        // clear the thumb bit
+        if let Some(handler) = addr2name.get(&(address & !1)) {
+            vectors.push(Vector {
+                number: number as u16,
+                handler,
+            });
+        } else {
+            warning!(
+                diagnostics,
+                Rule::Preemption,
+                "vector table: no symbol at address {:#010x} (exception number {})",
+                address,
+                number
+            );
+        }
+    }
+
+    vectors
+}
+
+// `cortex-m-rt` places the vector table in its own output section; if it's not there (e.g. custom
+// linker script) we look for the `__EXCEPTIONS` and `__INTERRUPTS` symbols
+fn table<'a>(elf: &ElfFile<'a>) -> Option<&'a [u8]> {
+    if let Some(sect) = elf.find_section_by_name(".vector_table") {
+        if sect.get_type() == Ok(ShType::ProgBits) {
+            return Some(sect.raw_data(elf));
+        }
+    }
+
+    let symtab = elf.find_section_by_name(".symtab")?;
+    let (mut exceptions, mut interrupts) = (None, None);
+    if let Ok(SectionData::SymbolTable32(entries)) = symtab.get_data(elf) {
+        for entry in entries {
+            match entry.get_name(elf) {
+                Ok("__EXCEPTIONS") => exceptions = Some((entry.value(), entry.size())),
+                Ok("__INTERRUPTS") => interrupts = Some((entry.value(), entry.size())),
+                _ => {}
+            }
+        }
+    }
+
+    // `__EXCEPTIONS` starts at the third entry of the vector table
+    let (exceptions, size) = exceptions?;
+    let start = exceptions.checked_sub(8)?;
+    let end = interrupts
+        .map(|(addr, size)| addr + size)
+        .unwrap_or(exceptions + size);
+
+    elf.section_iter().find_map(|sect| {
+        let addr = sect.address();
+
+        if sect.get_type() == Ok(ShType::ProgBits) && addr <= start && end <= addr + sect.size() {
+            Some(&sect.raw_data(elf)[(start - addr) as usize..(end - addr) as usize])
+        } else {
+            None
+        }
+    })
+}
+
+/// Computes the worst case stack usage of the whole system: the stack usage of thread mode plus
+/// that of the heaviest handler of each nesting level, plus one exception frame per level
+///
+/// `frame` is the size of the exception frame (see `frame_size`)
+pub fn analyze(
+    cg: &CallGraph,
+    priorities: &Priorities,
+    frame: u64,
+) -> (Option<System>, Vec<Diagnostic>) {
+    let g = &cg.graph;
+    let mut diagnostics = vec![];
+
+    if cg.vectors.is_empty() {
+        error!(
+            diagnostics,
+            Rule::Preemption,
+            "no vector table found; the system-wide analysis only supports Cortex-M programs"
+        );
+        return (None, diagnostics);
+    }
+
+    let indices = g
+        .node_indices()
+        .filter(|idx| !g[*idx].dashed)
+        .map(|idx| (&*g[idx].name, idx))
+        .collect::<HashMap<_, _>>();
+
+    // handlers may service more than one exception (e.g. `DefaultHandler`)
+    let mut thread = None;
+    let mut handlers = BTreeMap::<NodeIndex, Vec<Vector>>::new();
+    let mut missing = BTreeMap::<&str, Vec<Vector>>::new();
+    for vector in &cg.vectors {
+        if let Some(idx) = indices.get(vector.handler) {
+            if vector.number == 1 {
+                thread = Some(*idx);
+            } else {
+                handlers.entry(*idx).or_default().push(*vector);
+            }
+        } else if vector.number != 1 {
+            missing.entry(vector.handler).or_default().push(*vector);
+        }
+    }
+
+    // e.g. the call graph was filtered using a start point
+    for (handler, vectors) in missing {
+        error!(
+            diagnostics,
+            Rule::Preemption,
+            function = handler,
+            "`{}` ({}) is not in the call graph; the system-wide worst case doesn't account for it",
+            rustc_demangle::demangle(handler),
+            vectors
+                .iter()
+                .map(|vector| vector.exception())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let thread = if let Some(thread) = thread {
+        thread
+    } else {
+        error!(
+            diagnostics,
+            Rule::Preemption,
+            "the `Reset` handler is not in the call graph"
+        );
+        return (None, diagnostics);
+    };
+
+    let mut assigned = HashMap::new();
+    for (name, priority) in &priorities.handlers {
+        let hits = handlers
+            .iter()
+            .filter(|(idx, vectors)| {
+                let node = &g[**idx];
+
+                node.name == *name
+                    || node.demangled == *name
+                    || dehash(&rustc_demangle::demangle(&node.name).to_string()) == Some(name)
+                    || vectors.iter().any(|vector| vector.exception() == *name)
+            })
+            .map(|(idx, _)| *idx)
+            .collect::<Vec<_>>();
+
+        match &hits[..] {
+            [] => {
+                error!(
+                    diagnostics,
+                    Rule::Preemption,
+                    "priority: `{}` is not an exception handler in the call graph",
+                    name
+                );
+            }
+
+            [idx] => {
+                assigned.insert(*idx, *priority);
+            }
+
+            _ => {
+                let hits = hits.iter().map(|idx| &g[*idx].name).collect::<Vec<_>>();
+                error!(
+                    diagnostics,
+                    Rule::Preemption,
+                    "priority: multiple matches for `{}`: {:?}",
+                    name,
+                    hits
+                );
+            }
+        }
+    }
+
+    let mut levels = BTreeMap::<Priority, (NodeIndex, Max)>::new();
+    let mut unknown = vec![];
+    for (idx, vectors) in &handlers {
+        let handler_max = if let Some(max) = g[*idx].max {
+            max
+        } else {
+            error!(
+                diagnostics,
+                Rule::Preemption,
+                function = g[*idx].name,
+                "the max stack usage of `{}` is unknown (the analysis was skipped)",
+                g[*idx].demangled
+            );
+            return (None, diagnostics);
+        };
+
+        let fixed = vectors.iter().map(|vector| vector.number).min();
+        let priority = match fixed {
+            Some(2) => Priority::Nmi,
+            Some(3) => Priority::HardFault,
+            _ => {
+                if let Some(priority) = assigned.get(idx) {
+                    Priority::Logical(*priority)
+                } else {
+                    warning!(
+                        diagnostics,
+                        Rule::Preemption,
+                        function = g[*idx].name,
+                        "no priority given for `{}`; assuming it can preempt all other \
+                         handlers",
+                        g[*idx].demangled
+                    );
+                    unknown.push((Priority::Unknown, *idx));
+                    continue;
+                }
+            }
+        };
+
+        if fixed.map(|n| n <= 3).unwrap_or(false) && assigned.contains_key(idx) {
+            warning!(
+                diagnostics,
+                Rule::Preemption,
+                function = g[*idx].name,
+                "`{}` has a fixed priority; ignoring the given priority",
+                g[*idx].demangled
+            );
+        }
+
+        levels
+            .entry(priority)
+            .and_modify(|(heaviest, heaviest_max)| {
+                if handler_max.value() > heaviest_max.value() {
+                    *heaviest = *idx;
+                }
+
+                *heaviest_max = max(*heaviest_max, handler_max);
+            })
+            .or_insert((*idx, handler_max));
+    }
+
+    let mut total = if let Some(max) = g[thread].max {
+        max
+    } else {
+        error!(
+            diagnostics,
+            Rule::Preemption,
+            function = g[thread].name,
+            "the max stack usage of `{}` is unknown (the analysis was skipped)",
+            g[thread].demangled
+        );
+        return (None, diagnostics);
+    };
+
+    let mut nesting = vec![];
+    for (priority, (idx, level_max)) in levels {
+        total = total + level_max + Max::Exact(frame);
+        nesting.push((priority, idx, level_max));
+    }
+
+    for (priority, idx) in unknown {
+        let level_max = g[idx].max.expect("UNREACHABLE");
+        total = total + level_max + Max::Exact(frame);
+        nesting.push((priority, idx, level_max));
+    }
+
+    // `Unknown` goes between the configurable and the fixed priorities
+    nesting.sort_by_key(|(priority, ..)| *priority);
+
+    (
+        Some(System {
+            thread,
+            levels: nesting,
+            frame,
+            max: total,
+        }),
+        diagnostics,
+    )
+}
+
+/// Writes a breakdown of the system-wide worst case stack usage
+pub fn system<W>(cg: &CallGraph, system: &System, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+
+    writeln!(stdout, "system: max {}", system.max)?;
+    writeln!(stdout)?;
+    writeln!(
+        stdout,
+        "{:>9}  {:>8}  {:>8}  {:>8}  handler",
+        "priority", "max", "frame", "total"
+    )?;
+
+    let thread = g[system.thread].max.expect("UNREACHABLE");
+    let mut total = thread;
+    write!(
+        stdout,
+        "{:>9}  {:>8}  {:>8}  {:>8}  {}",
+        "thread",
+        bytes(thread),
+        0,
+        bytes(total),
+        g[system.thread].demangled
+    )?;
+    defined_at(g[system.thread].location, &mut stdout)?;
+
+    for (priority, idx, handler) in &system.levels {
+        let node = &g[*idx];
+        total = total + *handler + Max::Exact(system.frame);
+
+        let priority = match priority {
+            Priority::Logical(n) => n.to_string(),
+            Priority::Unknown => "?".to_owned(),
+            Priority::HardFault => "-1".to_owned(),
+            Priority::Nmi => "-2".to_owned(),
+        };
+
+        write!(
+            stdout,
+            "{:>9}  {:>8}  {:>8}  {:>8}  {}",
+            priority,
+            bytes(*handler),
+            system.frame,
+            bytes(total),
+            node.demangled
+        )?;
+        defined_at(node.location, &mut stdout)?;
+    }
+
+    Ok(())
+}
+
+// ends the line of a handler with its location, if known
+fn defined_at<W>(location: Option<Location>, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    if let Some(location) = location {
+        writeln!(stdout, " (defined at {})", location)
+    } else {
+        writeln!(stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+
+    use super::{Priorities, Priority, Vector};
+    use crate::{CallGraph, Level, Max, Node, Rule};
+
+    #[test]
+    fn analyze() {
+        let mut g = DiGraph::new();
+        let reset = g.add_node(Node("Reset", Some(88), false));
+        let systick = g.add_node(Node("SysTick", Some(24), false));
+        let exti0 = g.add_node(Node("EXTI0", None, false));
+        let exti1 = g.add_node(Node("EXTI1", Some(16), false));
+        g[reset].max = Some(Max::Exact(88));
+        g[systick].max = Some(Max::Exact(24));
+        g[exti0].max = Some(Max::LowerBound(8));
+        g[exti1].max = Some(Max::Exact(16));
+
+        let vector = |number, handler| Vector { number, handler };
+        let cg = CallGraph {
+            graph: g,
+            cycles: vec![],
+            cycle_locals: vec![],
+            start: None,
+            vectors: vec![
+                vector(1, "Reset"),
+                vector(15, "SysTick"),
+                vector(22, "EXTI0"),
+                vector(23, "EXTI1"),
+                // filtered out of the call graph
+                vector(24, "EXTI2"),
+            ],
+            diagnostics: vec![],
+            assumptions: vec![],
+        };
+
+        let priorities =
+            Priorities::parse(vec!["SysTick=1", "EXTI0=2", "EXTI1=2", "EXTI2=3"].into_iter())
+                .unwrap();
+        let (system, diagnostics) = super::analyze(&cg, &priorities, 36);
+        let system = system.unwrap();
+
+        // the level of `EXTI0` and `EXTI1` is a lower bound because of `EXTI0`
+        assert_eq!(
+            system.levels,
+            vec![
+                (Priority::Logical(1), systick, Max::Exact(24)),
+                (Priority::Logical(2), exti1, Max::LowerBound(16)),
+            ]
+        );
+        assert_eq!(system.max, Max::LowerBound(88 + 24 + 16 + 2 * 36));
+        assert!(diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.level == Level::Error
+                && diagnostic.rule == Rule::Preemption
+                && diagnostic.function.as_deref() == Some("EXTI2")));
+
+        // the report adds up to `System.max`
+        let mut out = vec![];
+        super::system(&cg, &system, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().last().unwrap().contains(">=200"));
+    }
+
+    #[test]
+    fn frame_size() {
+        assert_eq!(super::frame_size("thumbv7m-none-eabi"), 36);
+        assert_eq!(super::frame_size("thumbv7em-none-eabihf"), 108);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Priorities::parse(vec!["SysTick=1", "EXTI0=3"].into_iter()).unwrap(),
+            Priorities {
+                handlers: vec![("SysTick", 1), ("EXTI0", 3)],
+            }
+        );
+
+        assert!(Priorities::parse(vec!["SysTick"].into_iter()).is_err());
+        assert!(Priorities::parse(vec!["SysTick=0"].into_iter()).is_err());
+        assert!(Priorities::parse(vec!["SysTick=256"].into_iter()).is_err());
+    }
+
+    #[test]
+    fn exception() {
+        let vector = |number| Vector {
+            number,
+            handler: "DefaultHandler",
+        };
+
+        assert_eq!(vector(3).exception(), "HardFault");
+        assert_eq!(vector(15).exception(), "SysTick");
+        assert_eq!(vector(16).exception(), "IRQ0");
+        assert_eq!(vector(47).exception(), "IRQ31");
+    }
+
+    #[test]
+    fn nesting_order() {
+        let mut priorities = vec![
+            Priority::Nmi,
+            Priority::Unknown,
+            Priority::Logical(2),
+            Priority::HardFault,
+            Priority::Logical(1),
+        ];
+        priorities.sort();
+
+        assert_eq!(
+            priorities,
+            vec![
+                Priority::Logical(1),
+                Priority::Logical(2),
+                Priority::Unknown,
+                Priority::HardFault,
+                Priority::Nmi,
+            ]
+        );
+    }
+}
@@ -0,0 +1,697 @@
+use std::collections::HashSet;
+
+/// Analyzes a subroutine and returns the targets of all its function calls (`JAL`, `C.JAL`,
+/// `JALR`, `C.JALR`) and of all its jumps and branches (`J`, `C.J`, `JR`, `BEQ`, etc.), plus
+/// whether this function performs an indirect function call or not
+///
+/// Targets are offsets relative to `address`, the start of the subroutine
+// NOTE unlike Thumb, the length of a RISC-V instruction is encoded in its first bits so we only
+// decode the instructions we are interested in and skip over the rest
+// Reference: The RISC-V Instruction Set Manual, Volume I: Unprivileged ISA (20191213)
+pub fn analyze(
+    bytes: &[u8],
+    address: u64,
+    rv64: bool,
+) -> (Vec<i64>, Vec<i64>, bool, bool, Option<u64>) {
+    let instructions = decode(bytes, address, rv64);
+
+    // register values are unknown at the targets of intra-function branches because we don't know
+    // from where we got there
+    let joins = instructions
+        .iter()
+        .filter_map(|(_, instr)| match *instr {
+            Instruction::Jal { rd: ZERO, target } | Instruction::Branch { target } => Some(target),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    // we'll compute the stack usage like we do for Thumb: we only care about the prologue of
+    // trampolines so we give up the analysis if we encounter conditionals or loops
+    let mut modifies_sp = false;
+    let mut stack = Some(0);
+
+    // we track the values that `lui`, `auipc` and `addi` materialize in registers, mainly to
+    // resolve the target of `auipc ra, %hi(f)` + `jalr ra, %lo(f)(ra)` (`call f`) and the size of
+    // large stack frames (`lui t0, 1` + `sub sp, sp, t0`)
+    let mut regs = [None; 32];
+    let xlen = |value: i64| if rv64 { value } else { value as u32 as i64 };
+    // `value` as a signed XLEN integer
+    let signed = |value: i64| if rv64 { value } else { value as i32 as i64 };
+
+    let end = address + bytes.len() as u64;
+    let within = |target: u64| target >= address && target < end;
+
+    let mut bls = vec![];
+    let mut bs = vec![];
+    let mut indirect = false;
+    for (pc, instr) in instructions {
+        if joins.contains(&pc) {
+            regs = [None; 32];
+        }
+
+        // `x0` is hardwired to zero
+        regs[ZERO] = Some(0);
+
+        // `addi sp, sp, imm` and `add sp, sp, rs2` are the stack adjustments we understand (see
+        // below); any other write to SP (e.g. `mv sp, a0` or `andi sp, sp, -16`) moves the stack
+        // pointer by an amount we can't compute
+        let adjusts_sp = match instr {
+            Instruction::AddI { rs1: SP, .. } | Instruction::Add { rs1: SP, .. } => {
+                regs[SP].is_none()
+            }
+            _ => false,
+        };
+        if instr.rd() == Some(SP) && !adjusts_sp {
+            modifies_sp = true;
+            stack = None;
+        }
+
+        match instr {
+            Instruction::Lui { rd, imm } => regs[rd] = Some(xlen(imm)),
+
+            Instruction::Auipc { rd, imm } => regs[rd] = Some(xlen(pc as i64 + imm)),
+
+            Instruction::AddI { rd, rs1, imm, word } => {
+                if rd == SP && rs1 == SP && regs[SP].is_none() {
+                    // e.g. `addi sp, sp, -32`
+                    if imm < 0 {
+                        modifies_sp = true;
+
+                        if let Some(stack) = stack.as_mut() {
+                            *stack += (-imm) as u64;
+                        }
+                    }
+
+                    continue;
+                }
+
+                regs[rd] = regs[rs1].map(|value| {
+                    if word {
+                        i64::from((value + imm) as i32)
+                    } else {
+                        xlen(value + imm)
+                    }
+                });
+            }
+
+            Instruction::Add { rd, rs1, rs2, sub } => {
+                if rd == SP && rs1 == SP && regs[SP].is_none() {
+                    // e.g. `sub sp, sp, t0` where `t0` holds the size of a large stack frame
+                    match regs[rs2].map(signed) {
+                        Some(value) => {
+                            let allocated = if sub { value } else { -value };
+
+                            if allocated > 0 {
+                                modifies_sp = true;
+
+                                if let Some(stack) = stack.as_mut() {
+                                    *stack += allocated as u64;
+                                }
+                            }
+                        }
+
+                        None => {
+                            // dynamic stack allocation or something we don't understand
+                            modifies_sp = true;
+                            stack = None;
+                        }
+                    }
+
+                    continue;
+                }
+
+                regs[rd] = match (regs[rs1], regs[rs2]) {
+                    (Some(lhs), Some(rhs)) => Some(xlen(if sub { lhs - rhs } else { lhs + rhs })),
+                    _ => None,
+                };
+            }
+
+            Instruction::Jal { rd, target } => {
+                let offset = target as i64 - address as i64;
+
+                if rd == ZERO {
+                    // `j`; this is either an `if` / `loop` or a tail call
+                    if within(target) {
+                        // give up the stack usage analysis
+                        stack = None;
+                    }
+
+                    bs.push(offset);
+                } else {
+                    if rd == T0 {
+                        // call to a millicode routine (e.g. `__riscv_save_0`) which adjusts the
+                        // stack pointer on behalf of the caller
+                        stack = None;
+                    }
+
+                    bls.push(offset);
+
+                    // the callee may have clobbered all the caller-saved registers
+                    regs = [None; 32];
+                }
+            }
+
+            Instruction::Jalr { rd, rs1, imm } => {
+                let is_link = |reg| reg == RA || reg == T0;
+
+                if !is_link(rd) && is_link(rs1) {
+                    // `ret`
+                    continue;
+                }
+
+                if let Some(base) = regs[rs1] {
+                    let target = xlen(base + imm) as u64;
+                    let offset = target as i64 - address as i64;
+
+                    if rd == ZERO {
+                        // `auipc t1, %hi(f)` + `jalr zero, %lo(f)(t1)` (`tail f`)
+                        if within(target) {
+                            stack = None;
+                        }
+
+                        bs.push(offset);
+                    } else {
+                        bls.push(offset);
+                    }
+                } else {
+                    indirect = true;
+                }
+
+                if rd != ZERO {
+                    regs = [None; 32];
+                }
+            }
+
+            Instruction::Branch { target } => {
+                if within(target) {
+                    // this is an `if` or `loop`; give up the stack usage analysis
+                    stack = None;
+                }
+
+                bs.push(target as i64 - address as i64);
+            }
+
+            Instruction::Write { rd } => regs[rd] = None,
+
+            Instruction::Other => {}
+        }
+    }
+
+    (bls, bs, indirect, modifies_sp, stack)
+}
+
+const ZERO: usize = 0;
+const RA: usize = 1;
+const SP: usize = 2;
+const T0: usize = 5;
+
+// the instructions we care about; `target`s are absolute addresses
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instruction {
+    // `lui`, `c.lui`
+    Lui {
+        rd: usize,
+        imm: i64,
+    },
+
+    // `auipc`
+    Auipc {
+        rd: usize,
+        imm: i64,
+    },
+
+    // `addi`, `addiw`, `c.addi`, `c.addiw`, `c.addi16sp`, `c.li`
+    AddI {
+        rd: usize,
+        rs1: usize,
+        imm: i64,
+        // `addiw` / `c.addiw`: 32-bit operation on RV64
+        word: bool,
+    },
+
+    // `add`, `sub`, `c.add`, `c.mv`
+    Add {
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        sub: bool,
+    },
+
+    // `jal`, `c.jal`, `c.j`
+    Jal {
+        rd: usize,
+        target: u64,
+    },
+
+    // `jalr`, `c.jr`, `c.jalr`
+    Jalr {
+        rd: usize,
+        rs1: usize,
+        imm: i64,
+    },
+
+    // `beq`, `bne`, `c.beqz`, etc.
+    Branch {
+        target: u64,
+    },
+
+    // some other instruction that writes to the integer register `rd`
+    Write {
+        rd: usize,
+    },
+
+    // an instruction that doesn't write to an integer register (stores, `fence`, etc.)
+    Other,
+}
+
+impl Instruction {
+    // the integer register this instruction writes to, if any
+    fn rd(&self) -> Option<usize> {
+        match *self {
+            Instruction::Lui { rd, .. }
+            | Instruction::Auipc { rd, .. }
+            | Instruction::AddI { rd, .. }
+            | Instruction::Add { rd, .. }
+            | Instruction::Jal { rd, .. }
+            | Instruction::Jalr { rd, .. }
+            | Instruction::Write { rd } => Some(rd),
+            Instruction::Branch { .. } | Instruction::Other => None,
+        }
+    }
+}
+
+// returns `(address, instruction)` pairs
+fn decode(bytes: &[u8], address: u64, rv64: bool) -> Vec<(u64, Instruction)> {
+    let mut instructions = vec![];
+    let mut i = 0;
+    while i + 2 <= bytes.len() {
+        let pc = address + i as u64;
+        let first = u16::from(bytes[i]) | u16::from(bytes[i + 1]) << 8;
+
+        if first & 0b11 != 0b11 {
+            instructions.push((pc, compressed(first, pc, rv64)));
+            i += 2;
+        } else if i + 4 <= bytes.len() {
+            let second = u16::from(bytes[i + 2]) | u16::from(bytes[i + 3]) << 8;
+            instructions.push((pc, standard(u32::from(first) | u32::from(second) << 16, pc)));
+            i += 4;
+        } else {
+            break;
+        }
+    }
+
+    instructions
+}
+
+// 32-bit instruction
+fn standard(word: u32, pc: u64) -> Instruction {
+    let opcode = word & 0x7f;
+    let rd = (word >> 7 & 0x1f) as usize;
+    let funct3 = word >> 12 & 0b111;
+    let rs1 = (word >> 15 & 0x1f) as usize;
+    let rs2 = (word >> 20 & 0x1f) as usize;
+    let funct7 = word >> 25;
+
+    // I-type immediate
+    let imm_i = i64::from(word as i32 >> 20);
+
+    match opcode {
+        // 2.4 Integer Computational Instructions
+        0b011_0111 => Instruction::Lui {
+            rd,
+            imm: i64::from((word & 0xffff_f000) as i32),
+        },
+
+        0b001_0111 => Instruction::Auipc {
+            rd,
+            imm: i64::from((word & 0xffff_f000) as i32),
+        },
+
+        0b001_0011 | 0b001_1011 if funct3 == 0b000 => Instruction::AddI {
+            rd,
+            rs1,
+            imm: imm_i,
+            word: opcode == 0b001_1011,
+        },
+
+        0b011_0011 if funct3 == 0b000 && (funct7 == 0 || funct7 == 0b010_0000) => {
+            Instruction::Add {
+                rd,
+                rs1,
+                rs2,
+                sub: funct7 == 0b010_0000,
+            }
+        }
+
+        // 2.5 Control Transfer Instructions
+        0b110_1111 => {
+            let imm = (word >> 31 & 1) << 20
+                | (word >> 21 & 0x3ff) << 1
+                | (word >> 20 & 1) << 11
+                | (word >> 12 & 0xff) << 12;
+
+            Instruction::Jal {
+                rd,
+                target: offset(pc, sign_extend(imm, 21)),
+            }
+        }
+
+        0b110_0111 if funct3 == 0b000 => Instruction::Jalr {
+            rd,
+            rs1,
+            imm: imm_i,
+        },
+
+        0b110_0011 => {
+            let imm = (word >> 31 & 1) << 12
+                | (word >> 25 & 0x3f) << 5
+                | (word >> 8 & 0xf) << 1
+                | (word >> 7 & 1) << 11;
+
+            Instruction::Branch {
+                target: offset(pc, sign_extend(imm, 13)),
+            }
+        }
+
+        // stores (integer and floating point), `fence`
+        0b010_0011 | 0b010_0111 | 0b000_1111 => Instruction::Other,
+
+        // floating point loads and computations write to the floating point registers; the few
+        // that write to integer registers (comparisons, conversions, moves) are `OP-FP`
+        0b000_0111 | 0b100_0011 | 0b100_0111 | 0b100_1011 | 0b100_1111 => Instruction::Other,
+
+        _ => Instruction::Write { rd },
+    }
+}
+
+// 16.8 RVC Instruction Set Listings
+fn compressed(half: u16, pc: u64, rv64: bool) -> Instruction {
+    let half = u32::from(half);
+    let quadrant = half & 0b11;
+    let funct3 = half >> 13;
+    let bit12 = half >> 12 & 1;
+    // full register specifiers
+    let rd = (half >> 7 & 0x1f) as usize;
+    let rs2 = (half >> 2 & 0x1f) as usize;
+    // 3-bit register specifiers (`x8` - `x15`)
+    let rd_ = 8 + (half >> 7 & 0b111) as usize;
+    let rs2_ = 8 + (half >> 2 & 0b111) as usize;
+
+    // CI-format immediate
+    let imm6 = sign_extend(bit12 << 5 | half >> 2 & 0x1f, 6);
+
+    match (quadrant, funct3) {
+        // `c.addi4spn`, `c.lw`, `c.ld` / `c.flw`, `c.fld`
+        (0b00, 0b000) | (0b00, 0b010) => Instruction::Write { rd: rs2_ },
+        (0b00, 0b011) if rv64 => Instruction::Write { rd: rs2_ },
+        (0b00, _) => Instruction::Other,
+
+        // `c.addi` (`c.nop` if `rd == 0`)
+        (0b01, 0b000) => Instruction::AddI {
+            rd,
+            rs1: rd,
+            imm: imm6,
+            word: false,
+        },
+
+        // `c.addiw`
+        (0b01, 0b001) if rv64 => Instruction::AddI {
+            rd,
+            rs1: rd,
+            imm: imm6,
+            word: true,
+        },
+
+        // `c.jal`
+        (0b01, 0b001) => Instruction::Jal {
+            rd: RA,
+            target: offset(pc, cj(half)),
+        },
+
+        // `c.li`
+        (0b01, 0b010) => Instruction::AddI {
+            rd,
+            rs1: ZERO,
+            imm: imm6,
+            word: false,
+        },
+
+        // `c.addi16sp`
+        (0b01, 0b011) if rd == SP => {
+            let imm = bit12 << 9
+                | (half >> 6 & 1) << 4
+                | (half >> 5 & 1) << 6
+                | (half >> 3 & 0b11) << 7
+                | (half >> 2 & 1) << 5;
+
+            Instruction::AddI {
+                rd: SP,
+                rs1: SP,
+                imm: sign_extend(imm, 10),
+                word: false,
+            }
+        }
+
+        // `c.lui`
+        (0b01, 0b011) => Instruction::Lui {
+            rd,
+            imm: imm6 << 12,
+        },
+
+        // `c.srli`, `c.srai`, `c.andi`, `c.sub`, `c.xor`, `c.or`, `c.and`, `c.subw`, `c.addw`
+        (0b01, 0b100) => Instruction::Write { rd: rd_ },
+
+        // `c.j`
+        (0b01, 0b101) => Instruction::Jal {
+            rd: ZERO,
+            target: offset(pc, cj(half)),
+        },
+
+        // `c.beqz`, `c.bnez`
+        (0b01, _) => {
+            let imm = bit12 << 8
+                | (half >> 10 & 0b11) << 3
+                | (half >> 5 & 0b11) << 6
+                | (half >> 3 & 0b11) << 1
+                | (half >> 2 & 1) << 5;
+
+            Instruction::Branch {
+                target: offset(pc, sign_extend(imm, 9)),
+            }
+        }
+
+        // `c.slli`, `c.lwsp`, `c.ldsp` / `c.fldsp`, `c.flwsp`
+        (0b10, 0b000) | (0b10, 0b010) => Instruction::Write { rd },
+        (0b10, 0b011) if rv64 => Instruction::Write { rd },
+
+        // `c.jr`
+        (0b10, 0b100) if bit12 == 0 && rs2 == 0 => Instruction::Jalr {
+            rd: ZERO,
+            rs1: rd,
+            imm: 0,
+        },
+
+        // `c.mv`
+        (0b10, 0b100) if bit12 == 0 => Instruction::Add {
+            rd,
+            rs1: ZERO,
+            rs2,
+            sub: false,
+        },
+
+        // `c.ebreak`
+        (0b10, 0b100) if rd == 0 && rs2 == 0 => Instruction::Other,
+
+        // `c.jalr`
+        (0b10, 0b100) if rs2 == 0 => Instruction::Jalr {
+            rd: RA,
+            rs1: rd,
+            imm: 0,
+        },
+
+        // `c.add`
+        (0b10, 0b100) => Instruction::Add {
+            rd,
+            rs1: rd,
+            rs2,
+            sub: false,
+        },
+
+        // stores, `c.fldsp` / `c.flwsp`
+        _ => Instruction::Other,
+    }
+}
+
+// CJ-format immediate
+fn cj(half: u32) -> i64 {
+    let imm = (half >> 12 & 1) << 11
+        | (half >> 11 & 1) << 4
+        | (half >> 9 & 0b11) << 8
+        | (half >> 8 & 1) << 10
+        | (half >> 7 & 1) << 6
+        | (half >> 6 & 1) << 7
+        | (half >> 3 & 0b111) << 1
+        | (half >> 2 & 1) << 5;
+
+    sign_extend(imm, 12)
+}
+
+fn offset(pc: u64, imm: i64) -> u64 {
+    (pc as i64 + imm) as u64
+}
+
+fn sign_extend(x: u32, nbits: u32) -> i64 {
+    let shift = 32 - nbits;
+    i64::from((x << shift) as i32 >> shift)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn calls() {
+        // 2201            jal     256
+        assert_eq!(super::analyze(&[0x01, 0x22], 0, false).0, vec![256]);
+
+        // 040000ef        jal     64
+        assert_eq!(
+            super::analyze(&[0xef, 0x00, 0x00, 0x04], 0x100, false).0,
+            vec![64]
+        );
+
+        // 00001097        auipc   ra, 1
+        // ff0080e7        jalr    -16(ra)
+        let call = super::analyze(&[0x97, 0x10, 0x00, 0x00, 0xe7, 0x80, 0x00, 0xff], 0, false);
+        assert_eq!(call.0, vec![4096 - 16]);
+        assert!(!call.2);
+
+        // 800007b7        lui     a5, 0x80000
+        // 10078793        addi    a5, a5, 256
+        // 9782            jalr    a5
+        assert_eq!(
+            super::analyze(
+                &[0xb7, 0x07, 0x00, 0x80, 0x93, 0x87, 0x07, 0x10, 0x82, 0x97],
+                0x8000_0000,
+                false,
+            )
+            .0,
+            vec![256]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // b7c5            j       -32
+        assert_eq!(
+            super::analyze(&[0xc5, 0xb7], 0, false),
+            (vec![], vec![-32], false, false, Some(0))
+        );
+
+        // 00001317        auipc   t1, 1
+        // 00030067        jr      t1
+        assert_eq!(
+            super::analyze(&[0x17, 0x13, 0x00, 0x00, 0x67, 0x00, 0x03, 0x00], 0, false).1,
+            vec![4096]
+        );
+
+        // c501            beqz    a0, 8
+        // 8082            ret
+        assert_eq!(
+            super::analyze(&[0x01, 0xc5, 0x82, 0x80], 0, false),
+            (vec![], vec![8], false, false, Some(0))
+        );
+
+        // c111            beqz    a0, 4
+        // 8082            ret
+        // 8082            ret
+        assert_eq!(
+            super::analyze(&[0x11, 0xc1, 0x82, 0x80, 0x82, 0x80], 0, false).4,
+            None
+        );
+    }
+
+    #[test]
+    fn indirect() {
+        // 9782            jalr    a5
+        assert!(super::analyze(&[0x82, 0x97], 0, false).2);
+
+        // 8502            jr      a0
+        assert!(super::analyze(&[0x02, 0x85], 0, false).2);
+
+        // 8082            ret
+        assert!(!super::analyze(&[0x82, 0x80], 0, false).2);
+
+        // 00001517        auipc   a0, 1
+        // c191            beqz    a1, 4
+        // 8532            mv      a0, a2
+        // 9502            jalr    a0
+        // 8082            ret
+        // (the value of `a0` is unknown after the join point)
+        assert!(
+            super::analyze(
+                &[0x17, 0x15, 0x00, 0x00, 0x91, 0xc1, 0x32, 0x85, 0x02, 0x95, 0x82, 0x80],
+                0,
+                false
+            )
+            .2
+        );
+    }
+
+    #[test]
+    fn modifies_sp() {
+        // 0001            nop
+        let nop = super::analyze(&[0x01, 0x00], 0, false);
+        assert!(!nop.3);
+        assert_eq!(nop.4, Some(0));
+
+        // 1101            addi    sp, sp, -32
+        let addi = super::analyze(&[0x01, 0x11], 0, false);
+        assert!(addi.3);
+        assert_eq!(addi.4, Some(32));
+
+        // 7139            addi    sp, sp, -64
+        let addi16sp = super::analyze(&[0x39, 0x71], 0, true);
+        assert!(addi16sp.3);
+        assert_eq!(addi16sp.4, Some(64));
+
+        // 81010113        addi    sp, sp, -2032
+        // 6285            lui     t0, 1
+        // bf028293        addi    t0, t0, -1040
+        // 40510133        sub     sp, sp, t0
+        let large = super::analyze(
+            &[
+                0x13, 0x01, 0x01, 0x81, 0x85, 0x62, 0x93, 0x82, 0x02, 0xbf, 0x33, 0x01, 0x51, 0x40,
+            ],
+            0,
+            false,
+        );
+        assert!(large.3);
+        assert_eq!(large.4, Some(2032 + 4096 - 1040));
+
+        // 6141            addi    sp, sp, 16
+        let dealloc = super::analyze(&[0x41, 0x61], 0, false);
+        assert!(!dealloc.3);
+        assert_eq!(dealloc.4, Some(0));
+
+        // 00001117        auipc   sp, 1
+        // ff010113        addi    sp, sp, -16
+        // (this is `la sp, _stack_start`; SP now points to a different stack)
+        let la = super::analyze(&[0x17, 0x11, 0x00, 0x00, 0x13, 0x01, 0x01, 0xff], 0, false);
+        assert!(la.3);
+        assert_eq!(la.4, None);
+
+        // 812a            mv      sp, a0
+        let mv = super::analyze(&[0x2a, 0x81], 0, false);
+        assert!(mv.3);
+        assert_eq!(mv.4, None);
+
+        // ff017113        andi    sp, sp, -16
+        let andi = super::analyze(&[0x13, 0x71, 0x01, 0xff], 0, false);
+        assert!(andi.3);
+        assert_eq!(andi.4, None);
+
+        // 00a10133        add     sp, sp, a0
+        let add = super::analyze(&[0x33, 0x01, 0xa1, 0x00], 0, false);
+        assert!(add.3);
+        assert_eq!(add.4, None);
+    }
+}
@@ -0,0 +1,283 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::{find, ir, CallGraph, Diagnostic, Level, Rule};
+
+/// Version of the SARIF specification the output conforms to
+pub const VERSION: &str = "2.1.0";
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+#[derive(Serialize)]
+struct Log<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run<'a>>,
+}
+
+#[derive(Serialize)]
+struct Run<'a> {
+    tool: Tool,
+    results: Vec<Finding<'a>>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<ReportingDescriptor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingDescriptor {
+    id: &'static str,
+    name: &'static str,
+    short_description: Message<'static>,
+}
+
+// a SARIF `result`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Finding<'a> {
+    rule_id: &'static str,
+    rule_index: usize,
+    level: &'static str,
+    message: Message<'a>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<Location<'a>>,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    physical_location: Option<PhysicalLocation>,
+    logical_locations: Vec<LogicalLocation<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    region: Region,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_column: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogicalLocation<'a> {
+    fully_qualified_name: &'a str,
+    kind: &'static str,
+}
+
+impl From<ir::Location<'_>> for PhysicalLocation {
+    fn from(location: ir::Location) -> Self {
+        let path = location.path();
+        let uri = if path.starts_with('/') {
+            format!("file://{}", path)
+        } else {
+            path.into_owned()
+        };
+
+        PhysicalLocation {
+            artifact_location: ArtifactLocation { uri },
+            region: Region {
+                start_line: location.line,
+                start_column: location.column,
+            },
+        }
+    }
+}
+
+/// Writes the `diagnostics` as a SARIF log, for code scanning tools
+///
+/// Diagnostics about a function point to where the function is defined, if the call graph has
+/// debug information about it
+pub fn sarif<W>(cg: &CallGraph, diagnostics: &[Diagnostic], stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+
+    let results = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut locations = vec![];
+
+            if let Some(function) = &diagnostic.function {
+                let hits = find(g, function);
+
+                locations.push(if let [idx] = hits[..] {
+                    let node = &g[idx];
+
+                    Location {
+                        physical_location: node.location.map(PhysicalLocation::from),
+                        logical_locations: vec![LogicalLocation {
+                            fully_qualified_name: &node.demangled,
+                            kind: "function",
+                        }],
+                    }
+                } else {
+                    // not in the call graph or ambiguous
+                    Location {
+                        physical_location: None,
+                        logical_locations: vec![LogicalLocation {
+                            fully_qualified_name: function,
+                            kind: "function",
+                        }],
+                    }
+                });
+            }
+
+            Finding {
+                rule_id: diagnostic.rule.code(),
+                rule_index: Rule::ALL
+                    .iter()
+                    .position(|rule| *rule == diagnostic.rule)
+                    .expect("BUG: rule missing from `Rule::ALL`"),
+                level: match diagnostic.level {
+                    Level::Warning => "warning",
+                    Level::Error => "error",
+                },
+                message: Message {
+                    text: &diagnostic.message,
+                },
+                locations,
+            }
+        })
+        .collect();
+
+    let log = Log {
+        schema: SCHEMA,
+        version: VERSION,
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                    information_uri: env!("CARGO_PKG_REPOSITORY"),
+                    rules: Rule::ALL
+                        .iter()
+                        .map(|rule| ReportingDescriptor {
+                            id: rule.code(),
+                            name: rule.id(),
+                            short_description: Message {
+                                text: rule.description(),
+                            },
+                        })
+                        .collect(),
+                },
+            },
+            results,
+        }],
+    };
+
+    serde_json::to_writer_pretty(stdout, &log)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+    use serde_json::json;
+
+    use crate::{ir::Location, CallGraph, Diagnostic, Level, Node, Rule};
+
+    #[test]
+    fn sarif() {
+        let mut g = DiGraph::new();
+        let foo = g.add_node(Node("_ZN3app3foo17h0123456789abcdefE", None, false));
+        g[foo].location = Some(Location {
+            file: "src/main.rs",
+            directory: None,
+            line: 12,
+            column: Some(4),
+        });
+
+        let cg = CallGraph {
+            graph: g,
+            cycles: vec![],
+            cycle_locals: vec![],
+            start: None,
+            vectors: vec![],
+            diagnostics: vec![],
+            assumptions: vec![],
+        };
+
+        let diagnostics = vec![
+            Diagnostic {
+                level: Level::Warning,
+                rule: Rule::NoStackInfo,
+                function: Some("app::foo".to_string()),
+                message: "no stack usage information for `app::foo`".to_string(),
+            },
+            Diagnostic {
+                level: Level::Error,
+                rule: Rule::Input,
+                function: None,
+                message: "start point not found; the graph will not be filtered".to_string(),
+            },
+        ];
+
+        let mut out = vec![];
+        super::sarif(&cg, &diagnostics, &mut out).unwrap();
+        let log: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(
+            log["runs"][0]["results"],
+            json!([
+                {
+                    "ruleId": "CS001",
+                    "ruleIndex": 0,
+                    "level": "warning",
+                    "message": { "text": "no stack usage information for `app::foo`" },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": "src/main.rs" },
+                            "region": { "startLine": 12, "startColumn": 4 }
+                        },
+                        "logicalLocations": [{
+                            "fullyQualifiedName": "app::foo::h0123456789abcdef",
+                            "kind": "function"
+                        }]
+                    }]
+                },
+                {
+                    "ruleId": "CS012",
+                    "ruleIndex": 11,
+                    "level": "error",
+                    "message": { "text": "start point not found; the graph will not be filtered" }
+                }
+            ])
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{cmp::Reverse, io};
+
+use petgraph::{graph::NodeIndex, Direction};
+
+use crate::{path, CallGraph, Local};
+
+/// Writes a plain text report of the call graph: the roots sorted by maximum stack usage, the
+/// `top` functions with the largest local stack usage, the functions with unknown stack usage, the
+/// cycles and the indirect calls
+pub fn summary<W>(cg: &CallGraph, top: usize, mut stdout: W) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let g = &cg.graph;
+
+    // nodes that have no callers
+    let mut roots = g
+        .node_indices()
+        .filter(|idx| {
+            g.neighbors_directed(*idx, Direction::Incoming)
+                .all(|caller| caller == *idx)
+        })
+        .collect::<Vec<_>>();
+    // largest first; nodes whose analysis was skipped go last
+    roots.sort_by_key(|idx| {
+        (
+            Reverse(g[*idx].max.map(|max| max.value())),
+            &g[*idx].demangled,
+        )
+    });
+
+    writeln!(stdout, "roots ({}):", roots.len())?;
+    writeln!(stdout, "{:>8}  function", "max")?;
+    for root in &roots {
+        let max = g[*root].max.map(path::bytes).unwrap_or_else(|| "?".into());
+        writeln!(stdout, "{:>8}  {}", max, g[*root].demangled)?;
+    }
+
+    let mut locals = g
+        .node_indices()
+        .filter_map(|idx| match g[idx].local {
+            Local::Exact(n) if !g[idx].dashed => Some((n, idx)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    locals.sort_by_key(|(n, idx)| (Reverse(*n), &g[*idx].demangled));
+    locals.truncate(top);
+
+    writeln!(stdout)?;
+    writeln!(
+        stdout,
+        "top {} functions by local stack usage:",
+        locals.len()
+    )?;
+    writeln!(stdout, "{:>8}  function", "local")?;
+    for (local, idx) in locals {
+        writeln!(stdout, "{:>8}  {}", local, g[idx].demangled)?;
+    }
+
+    let unknowns = g
+        .node_indices()
+        .filter(|idx| g[*idx].local == Local::Unknown)
+        .collect::<Vec<_>>();
+
+    writeln!(stdout)?;
+    writeln!(stdout, "unknown stack usage ({}):", unknowns.len())?;
+    for idx in unknowns {
+        let node = &g[idx];
+
+        write!(stdout, "- `{}`", node.demangled)?;
+        if node.name == "?" {
+            // there may be several of these; tell them apart by their callers
+            write!(stdout, " (called by")?;
+            for caller in g.neighbors_directed(idx, Direction::Incoming) {
+                write!(stdout, " `{}`", g[caller].demangled)?;
+            }
+            write!(stdout, ")")?;
+        }
+        if let Some(reason) = node.unknown {
+            write!(stdout, ": {}", reason)?;
+        }
+        writeln!(stdout)?;
+    }
+
+    writeln!(stdout)?;
+    writeln!(stdout, "cycles ({}):", cg.cycles.len())?;
+    for (i, (cycle, local)) in cg.cycles.iter().zip(&cg.cycle_locals).enumerate() {
+        write!(stdout, "- SCC{} (local {}):", i, path::bytes(*local))?;
+        for member in cycle {
+            write!(stdout, " `{}`", g[*member].demangled)?;
+        }
+        writeln!(stdout)?;
+    }
+
+    let calls = g
+        .node_indices()
+        .filter(|idx| g[*idx].dashed)
+        .collect::<Vec<_>>();
+
+    writeln!(stdout)?;
+    writeln!(stdout, "indirect calls ({}):", calls.len())?;
+    for idx in calls {
+        let kind = if g[idx].name.ends_with('*') {
+            "function pointer"
+        } else {
+            "dynamic dispatch"
+        };
+
+        writeln!(
+            stdout,
+            "- `{}` ({}): {} callees",
+            g[idx].demangled,
+            kind,
+            callees(cg, idx)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn callees(cg: &CallGraph, idx: NodeIndex) -> usize {
+    let mut callees = cg.graph.neighbors(idx).collect::<Vec<_>>();
+    callees.sort();
+    callees.dedup();
+    callees.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+
+    use crate::{CallGraph, Edge, Max, Node, Unknown};
+
+    #[test]
+    fn summary() {
+        let mut g = DiGraph::new();
+        let main = g.add_node(Node("main", Some(8), false));
+        let foo = g.add_node(Node("foo", Some(16), false));
+        let bar = g.add_node(Node("bar", Some(4), false));
+        let call = g.add_node(Node("fn()*", Some(0), true));
+        let unknown = g.add_node(Node("?", None, false));
+        let handler = g.add_node(Node("SysTick", Some(32), false));
+        g[unknown].unknown = Some(Unknown::IndirectCall);
+        g.add_edge(main, foo, Edge::default());
+        g.add_edge(foo, bar, Edge::default());
+        g.add_edge(bar, foo, Edge::default());
+        g.add_edge(main, call, Edge::default());
+        g.add_edge(call, bar, Edge::default());
+        g.add_edge(call, unknown, Edge::default());
+
+        g[main].max = Some(Max::LowerBound(28));
+        g[handler].max = Some(Max::Exact(32));
+
+        let cg = CallGraph {
+            graph: g,
+            cycles: vec![vec![foo, bar]],
+            cycle_locals: vec![Max::Exact(20)],
+            start: None,
+            vectors: vec![],
+            diagnostics: vec![],
+            assumptions: vec![],
+        };
+
+        let mut out = vec![];
+        super::summary(&cg, 2, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "roots (2):
+     max  function
+      32  SysTick
+    >=28  main
+
+top 2 functions by local stack usage:
+   local  function
+      32  SysTick
+      16  foo
+
+unknown stack usage (1):
+- `?` (called by `fn()*`): unknown callee of an indirect function call
+
+cycles (1):
+- SCC0 (local 20): `foo` `bar`
+
+indirect calls (1):
+- `fn()*` (function pointer): 2 callees
+"
+        );
+    }
+}
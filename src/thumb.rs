@@ -1,12 +1,19 @@
+use core::cmp;
+
 /// Analyzes a subroutine and returns all the `BL` and `B` instructions in it, plus whether this
 /// function performs an indirect function call or not
+///
+/// The stack usage is the maximum stack depth reached along all the paths of the control flow
+/// graph of the subroutine; it's `None` if SP is not balanced on return or if we can't follow the
+/// control flow or the SP (e.g. jump tables, dynamic stack allocations)
 // NOTE we assume that `bytes` is always valid input so all errors are bugs
 // Reference: ARMv7-M Architecture Reference Manual (ARM DDI 0403E.b)
 // Reference: ARMv6-M Architecture Reference Manual (ARM DDI 0419D)
+// Reference: ARMv8-M Architecture Reference Manual (ARM DDI 0553B.y)
 pub fn analyze(
     bytes: &[u8],
     address: u32,
-    v7: bool,
+    arch: Arch,
     tags: &[(u32, Tag)],
 ) -> (Vec<i32>, Vec<i32>, bool, bool, Option<u64>) {
     macro_rules! bug {
@@ -14,17 +21,28 @@ pub fn analyze(
             panic!(
                 "BUG: unknown instruction {:02x}{:02x}",
                 $first[1], $first[0]
-            );
+            )
         };
 
         ($first:expr, $second:expr) => {
             panic!(
                 "BUG: unknown instruction {:02x}{:02x} {:02x}{:02x}",
                 $first[1], $first[0], $second[1], $second[0]
-            );
+            )
         };
     }
 
+    const SP: u8 = 0b1101;
+    const LR: u8 = 0b1110;
+    const PC: u8 = 0b1111;
+
+    // Thumb-2 instructions are only available on ARMv7-M and ARMv8-M Mainline
+    let v7 = arch == Arch::V7M || arch == Arch::V8MMain;
+    // ARMv8-M Baseline gains a few of them: CBZ, CBNZ, B.W, MOVW and MOVT
+    let v8m_base = v7 || arch == Arch::V8MBase;
+    // the Security Extension (TrustZone) instructions: SG, BXNS, BLXNS and TT*
+    let v8m = arch == Arch::V8MBase || arch == Arch::V8MMain;
+
     // we want to know if any of the instructions modifies the SP (stack pointer). We use this
     // information to determine if the subroutine uses stack space or not. We want to detect the
     // following instructions:
@@ -35,12 +53,13 @@ pub fn analyze(
     // - f5ad 7d02       sub.w   sp, sp, #520    ; 0x208
     let mut modifies_sp = false;
 
-    // we'll try to compute the stack usage. We are mainly interested in `global_asm!` and
-    // `#[naked]` functions that only contain a single `asm!` block that only as trampolines. For
-    // that reason we'll give up the analysis if we encounter conditionals or loops, i.e.
-    // intra-branching, within the function. Analyzing those functions would be more work and won't
-    // help with our main goal of analyzing trampolines.
-    let mut stack = Some(0);
+    // we'll also compute the stack usage. We are mainly interested in `global_asm!` and `#[naked]`
+    // functions, for which LLVM reports no (or zero) stack usage. To that end we record the effect
+    // of each instruction on the SP and on the control flow and then walk the control flow graph
+    // (see `max_depth`)
+    let mut instructions = vec![];
+    // number of instructions left in the current IT block
+    let mut it_block = 0;
 
     // we want to avoid writing a full blown decoder since we are only interested in a single type
     // of instruction. We know that instructions can be 16-bit or 32-bit so we'll only decode 16-bit
@@ -81,6 +100,22 @@ pub fn analyze(
             }
         }
 
+        // the instructions in an IT block are conditional
+        let conditional = it_block != 0;
+        if conditional {
+            it_block -= 1;
+        }
+
+        // for now assume a 16-bit instruction that doesn't touch the SP
+        let current = instructions.len();
+        instructions.push(Instruction {
+            offset: 2 * i as u32,
+            size: 2,
+            sp: Some(0),
+            flow: Flow::Next,
+            conditional,
+        });
+
         if matches(first, "0b010000_0101_xxx_xxx") {
             // A7.7.2 ADC (register) - T1
             continue;
@@ -95,18 +130,25 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b010001_00_x_xxxx_xxx") {
             // A7.7.4 ADD (register) - T2
+            // (this includes A7.7.6 ADD (SP plus register))
+            let rdn = ((first[0] >> 4) & 0b1000) | (first[0] & 0b111);
+
+            if rdn == SP {
+                // e.g. `add sp, r4` where `r4` holds a value loaded from a literal pool
+                instructions[current].sp = None;
+            } else if rdn == PC {
+                instructions[current].flow = jump_table(bytes, address, tags, 2 * i as u32 + 2);
+            }
+
             continue;
         } else if matches(first, "0b1010_1_xxx_xxxxxxxx") {
             // A7.7.5  ADD (SP plus immediate) - T1
             continue;
         } else if matches(first, "0b1011_0000_0_xxxxxxx") {
             // A7.7.5  ADD (SP plus immediate) - T2
-            continue;
-        } else if matches(first, "0b01000100_x_1101_xxx") {
-            // A7.7.6  ADD (SP plus register) - T1
-            continue;
-        } else if matches(first, "0b01000100_1_xxxx_101") {
-            // A7.7.6  ADD (SP plus register) - T2
+            let imm7 = first[0] & 0b0111_1111;
+            instructions[current].sp = Some(-(i32::from(imm7) << 2));
+
             continue;
         } else if matches(first, "0b1010_0_xxx_xxxxxxxx") {
             // A7.7.7  ADR - T1
@@ -120,10 +162,12 @@ pub fn analyze(
         } else if matches(first, "0b010000_0100_xxx_xxx") {
             // A7.7.11  ASR (register) - T1
             continue;
-        } else if v7 && matches(first, "0b1101_1110_xxxxxxxx") {
+        } else if matches(first, "0b1101_1110_xxxxxxxx") {
             // NOTE we break the alphabetical order because the rule for `B` overlaps with the rule
             // for `UDF` but `UDF` takes precedence
             // A7.7.191      UDF - T1
+            instructions[current].flow = Flow::Stop;
+
             continue;
         } else if matches(first, "0b1101_1111_xxxxxxxx") {
             // NOTE we break the alphabetical order because the rule for `B` overlaps with the rule
@@ -143,11 +187,8 @@ pub fn analyze(
             // (it's unclear to me why this needs to be `4` instead of `2` but that's what works)
             imm32 += 2 * i + 4;
 
-            if imm32 >= 0 && (imm32 as usize) < bytes.len() {
-                // this is an `if` or `loop`; give up the stack usage analysis
-                stack = None;
-            }
-
+            instructions[current].flow = branch(imm32, bytes.len());
+            instructions[current].conditional = true;
             bs.push(imm32);
         } else if matches(first, "0b11100_xxxxxxxxxxx") {
             // A7.7.12  B - T2
@@ -159,11 +200,7 @@ pub fn analyze(
             // (it's unclear to me why this needs to be `4` instead of `2` but that's what works)
             imm32 += 2 * i + 4;
 
-            if imm32 >= 0 && (imm32 as usize) < bytes.len() {
-                // this is an `if` or `loop`; give up the stack usage analysis
-                stack = None;
-            }
-
+            instructions[current].flow = branch(imm32, bytes.len());
             bs.push(imm32);
         } else if matches(first, "0b010000_1110_xxx_xxx") {
             // A7.7.16  BIC (register) - T1
@@ -174,16 +211,42 @@ pub fn analyze(
         } else if matches(first, "0b010001_11_1_xxxx_000") {
             // A7.7.19  BLX (register) - T1
             indirect = true;
+        } else if v8m && matches(first, "0b010001_11_1_xxxx_100") {
+            // BLXNS - T1 (in ARMv8-M-ARM)
+            // a call into the Non-secure state through a function pointer
+            indirect = true;
         } else if matches(first, "0b010001_11_0_xxxx_000") {
             // A7.7.20  BX - T1
             let rm = (first[0] >> 3) & 0b1111;
 
-            // `bx lr` is just a `return`
-            if rm != 0b1110 {
+            // `bx lr` is just a `return`; anything else is either a jump through a jump table or
+            // an indirect tail call
+            instructions[current].flow = Flow::Return;
+            if rm != LR {
+                match jump_table(bytes, address, tags, 2 * i as u32 + 2) {
+                    Flow::Unknown => indirect = true,
+                    table => instructions[current].flow = table,
+                }
+            }
+        } else if v8m && matches(first, "0b010001_11_0_xxxx_100") {
+            // BXNS - T1 (in ARMv8-M-ARM)
+            // `bxns lr` returns from a Secure entry function to the Non-secure caller; anything else
+            // is an indirect tail call into the Non-secure state
+            let rm = (first[0] >> 3) & 0b1111;
+
+            if rm != LR {
                 indirect = true;
             }
-        } else if v7 && matches(first, "0b1011_x_0_x_1_xxxxx_xxx") {
+
+            instructions[current].flow = Flow::Return;
+        } else if v8m_base && matches(first, "0b1011_x_0_x_1_xxxxx_xxx") {
             // A7.7.21  CBNZ, CBZ - T1
+            let imm5 = i32::from(first[0] >> 3);
+            let i_ = i32::from((first[1] >> 1) & 1);
+            let imm32 = ((i_ << 6) | (imm5 << 1)) + 2 * i + 4;
+
+            instructions[current].flow = branch(imm32, bytes.len());
+            instructions[current].conditional = true;
             continue;
         } else if matches(first, "0b010000_1011_xxx_xxx") {
             // A7.7.26  CMN (register) - T1
@@ -205,6 +268,12 @@ pub fn analyze(
             continue;
         } else if v7 && matches(first, "0b1011_1111_xxxx_xxxx") {
             // A7.7.37  IT - T1
+            // (`mask == 0` encodes hints like NOP)
+            let mask = first[0] & 0b1111;
+            if mask != 0 {
+                it_block = 4 - mask.trailing_zeros();
+            }
+
             continue;
         } else if matches(first, "0b1100_1_xxx_xxxxxxxx") {
             // A7.7.40  LDM, LDMIA, LDMFD - T1
@@ -256,6 +325,16 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b010001_10_x_xxxx_xxx") {
             // A7.7.76  MOV (register) - T1
+            let rd = ((first[0] >> 4) & 0b1000) | (first[0] & 0b111);
+
+            if rd == SP {
+                // e.g. `mov sp, r7`, restoring the SP from the frame pointer
+                instructions[current].sp = None;
+            } else if rd == PC {
+                // e.g. `mov pc, r0` after loading `r0` from a jump table (ARMv6-M)
+                instructions[current].flow = jump_table(bytes, address, tags, 2 * i as u32 + 2);
+            }
+
             continue;
         } else if matches(first, "0b000_00_00000_xxx_xxx") {
             // A7.7.76  MOV (register) - T2
@@ -275,6 +354,16 @@ pub fn analyze(
             continue;
         } else if matches(first, "0b1011_1_10_x_xxxxxxxx") {
             // A7.7.98  POP - T1
+            // e.g. 'bd80            pop     {r7, pc}'
+            let p = first[1] & 1;
+            let register_list = first[0];
+            let registers = (u16::from(p) << 15) | u16::from(register_list);
+            instructions[current].sp = Some(-4 * registers.count_ones() as i32);
+
+            if p == 1 {
+                instructions[current].flow = Flow::Return;
+            }
+
             continue;
         } else if matches(first, "0b1011_0_10_x_xxxxxxxx") {
             // A7.7.99  PUSH - T1
@@ -284,9 +373,7 @@ pub fn analyze(
             let m = first[1] & 1;
             let register_list = first[0];
             let register = (u16::from(m) << 14) | u16::from(register_list);
-            if let Some(stack) = stack.as_mut() {
-                *stack += 4 * u64::from(register.count_ones());
-            }
+            instructions[current].sp = Some(4 * register.count_ones() as i32);
 
             continue;
         } else if matches(first, "0b1011_1010_00_xxx_xxx") {
@@ -349,11 +436,7 @@ pub fn analyze(
             modifies_sp = true;
 
             let imm7 = first[0] & 0b0111_1111;
-            let imm32 = u32::from(imm7) << 2;
-
-            if let Some(stack) = stack.as_mut() {
-                *stack += u64::from(imm32);
-            }
+            instructions[current].sp = Some(i32::from(imm7) << 2);
 
             continue;
         } else if matches(first, "0b1011_0010_01_xxx_xxx") {
@@ -382,8 +465,10 @@ pub fn analyze(
             continue;
         } else {
             let second = halfwords.next().unwrap_or_else(|| bug!(first)).0;
+            instructions[current].size = 4;
 
-            const SP: u8 = 0b1101;
+            // destination register of data processing instructions
+            let rd = second[1] & 0b1111;
 
             if v7
                 && matches(first, "0b11101_00_100_x_0_xxxx")
@@ -399,17 +484,13 @@ pub fn analyze(
                         ((u16::from(second[1]) & 0b0001_1111) << 8) | u16::from(second[0]);
                     let m = (second[1] >> 6) & 1;
                     let registers = (u16::from(m) << 14) | register_list;
-
-                    if let Some(stack) = stack.as_mut() {
-                        *stack += 4 * u64::from(registers.count_ones());
-                    }
+                    instructions[current].sp = Some(4 * registers.count_ones() as i32);
                 }
             } else if v7
                 && matches(first, "0b11110_x_0_1101_x_1101")
                 && matches(second, "0b0_xxx_xxxx_xxxxxxxx")
             {
                 // A7.7.173      SUB (SP minus immediate) - T2
-                let rd = second[1] & 0b1111;
                 if rd == SP {
                     modifies_sp = true;
 
@@ -419,10 +500,7 @@ pub fn analyze(
                     let imm32 = thumb_expand_imm(
                         (u16::from(i) << 11) | (u16::from(imm3) << 8) | u16::from(imm8),
                     );
-
-                    if let Some(stack) = stack.as_mut() {
-                        *stack += u64::from(imm32);
-                    }
+                    instructions[current].sp = Some(imm32 as i32);
                 }
             } else if v7
                 && matches(first, "0b1110_110_1_0_x_1_0_1101")
@@ -431,12 +509,8 @@ pub fn analyze(
                 // A7.7.249      VPUSH - T1
                 modifies_sp = true;
 
-                let imm8 = second[0] & 0b1111_1111;
-                let imm32 = u32::from(imm8) << 2;
-
-                if let Some(stack) = stack.as_mut() {
-                    *stack += u64::from(imm32);
-                }
+                let imm8 = second[0];
+                instructions[current].sp = Some(i32::from(imm8) << 2);
             } else if v7
                 && matches(first, "0b1110_110_1_0_x_1_0_1101")
                 && matches(second, "0bxxxx_1010_xxxxxxxx")
@@ -444,12 +518,139 @@ pub fn analyze(
                 // A7.7.249      VPUSH - T2
                 modifies_sp = true;
 
-                let imm8 = second[0] & 0b1111_1111;
-                let imm32 = u32::from(imm8) << 2;
+                let imm8 = second[0];
+                instructions[current].sp = Some(i32::from(imm8) << 2);
+            } else if v7
+                && matches(first, "0b1110_110_0_1_x_1_1_1101")
+                && (matches(second, "0bxxxx_1011_xxxxxxxx")
+                    || matches(second, "0bxxxx_1010_xxxxxxxx"))
+            {
+                // A7.7.248      VPOP - T1, T2
+                let imm8 = second[0];
+                instructions[current].sp = Some(-(i32::from(imm8) << 2));
+            } else if v7
+                && matches(first, "0b11101_00_010_1_1_1101")
+                && matches(second, "0bx_x_0_xxxxxxxxxxxxx")
+            {
+                // A7.7.98  POP - T2
+                // e.g. 'e8bd 81f0       pop.w   {r4, r5, r6, r7, r8, pc}'
+                let registers = (u16::from(second[1]) << 8) | u16::from(second[0]);
+                instructions[current].sp = Some(-4 * registers.count_ones() as i32);
 
-                if let Some(stack) = stack.as_mut() {
-                    *stack += u64::from(imm32);
+                if registers >> 15 == 1 {
+                    instructions[current].flow = Flow::Return;
                 }
+            } else if v7
+                && matches(first, "0b1111_1000_0101_1101")
+                && matches(second, "0bxxxx_1011_0000_0100")
+            {
+                // A7.7.98  POP - T3
+                // e.g. 'f85d fb04       ldr     pc, [sp], #4'
+                instructions[current].sp = Some(-4);
+
+                if second[1] >> 4 == PC {
+                    instructions[current].flow = Flow::Return;
+                }
+            } else if v7
+                && matches(first, "0b1111_1000_x101_xxxx")
+                && matches(second, "0b1111_xxxx_xxxxxxxx")
+            {
+                // A7.7.42 - A7.7.44  LDR (immediate, literal, register) with PC as the destination
+                instructions[current].flow = jump_table(bytes, address, tags, 2 * i as u32 + 4);
+            } else if v7
+                && matches(first, "0b1111_1000_0100_1101")
+                && matches(second, "0bxxxx_1101_0000_0100")
+            {
+                // A7.7.99  PUSH - T3
+                // e.g. 'f84d 4d04       str     r4, [sp, #-4]!'
+                modifies_sp = true;
+                instructions[current].sp = Some(4);
+            } else if v8m
+                && matches(first, "0b1110_1001_0111_1111")
+                && matches(second, "0b1110_1001_0111_1111")
+            {
+                // SG - T1 (in ARMv8-M-ARM)
+                // first instruction of the Secure gateway veneers of Secure entry functions
+                continue;
+            } else if v8m
+                && matches(first, "0b1110_1000_0100_xxxx")
+                && matches(second, "0b1111_xxxx_xx00_0000")
+            {
+                // TT, TTT, TTA, TTAT - T1 (in ARMv8-M-ARM)
+                continue;
+            } else if v8m_base
+                && (matches(first, "0b11110_x_10_0_1_0_0_xxxx")
+                    || matches(first, "0b11110_x_10_1_1_0_0_xxxx"))
+                && matches(second, "0b0_xxx_xxxx_xxxxxxxx")
+                && rd != SP
+            {
+                // A7.7.75  MOV (immediate) - T3
+                // A7.7.78  MOVT - T1
+                // (writes to the SP are handled below)
+                continue;
+            } else if v7
+                && matches(first, "0b1110_1000_1101_xxxx")
+                && matches(second, "0b1111_0000_000_x_xxxx")
+            {
+                // A7.7.185      TBB, TBH - T1
+                // e.g. 'e8df f000       tbb     [pc, r0]'
+                let rn = first[0] & 0b1111;
+                let half = (second[0] >> 4) & 1 == 1;
+
+                instructions[current].flow = if rn == PC {
+                    // the table follows the instruction
+                    table_branch(bytes, address, tags, 2 * i as u32 + 4, half)
+                } else {
+                    Flow::Unknown
+                };
+            } else if v7
+                && matches(first, "0b11110_x_0_1000_x_1101")
+                && matches(second, "0b0_xxx_xxxx_xxxxxxxx")
+                && rd == SP
+            {
+                // A7.7.5  ADD (SP plus immediate) - T3
+                let imm8 = second[0];
+                let imm3 = (second[1] >> 4) & 0b0111;
+                let i = (first[1] >> 2) & 1;
+                let imm32 = thumb_expand_imm(
+                    (u16::from(i) << 11) | (u16::from(imm3) << 8) | u16::from(imm8),
+                );
+                instructions[current].sp = Some(-(imm32 as i32));
+            } else if v7
+                && (matches(first, "0b11110_x_1_0000_0_1101")
+                    || matches(first, "0b11110_x_1_0101_0_1101"))
+                && matches(second, "0b0_xxx_xxxx_xxxxxxxx")
+                && rd == SP
+            {
+                // A7.7.5  ADD (SP plus immediate) - T4
+                // A7.7.173      SUB (SP minus immediate) - T3
+                let imm8 = second[0];
+                let imm3 = (second[1] >> 4) & 0b0111;
+                let i = (first[1] >> 2) & 1;
+                let imm12 = (i32::from(i) << 11) | (i32::from(imm3) << 8) | i32::from(imm8);
+
+                if (first[0] >> 5) & 1 == 1 {
+                    // SUBW
+                    modifies_sp = true;
+                    instructions[current].sp = Some(imm12);
+                } else {
+                    // ADDW
+                    instructions[current].sp = Some(-imm12);
+                }
+            } else if v7
+                && ((matches(first, "0b11110_x_xxxxxxxxxx")
+                    && matches(second, "0b0_xxx_xxxx_xxxxxxxx"))
+                    || matches(first, "0b1110101_xxxxxxxxx"))
+                && rd == SP
+            {
+                // A5.3.1, A5.3.3, A5.3.11  some other data processing instruction that writes to
+                // the SP, e.g. 'ebad 0d00       sub.w   sp, sp, r0' (dynamic stack allocation)
+                if matches(first, "0b11101_01_1101_x_1101") {
+                    // A7.7.174      SUB (SP minus register) - T1
+                    modifies_sp = true;
+                }
+
+                instructions[current].sp = None;
             } else if v7
                 && matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b10_x_0_x_xxxxxxxxxxx")
@@ -479,13 +680,10 @@ pub fn analyze(
                 // accordingly
                 imm32 += 2 * i + 4;
 
-                if imm32 >= 0 && (imm32 as usize) < bytes.len() {
-                    // this is an `if` or `loop`; give up the stack usage analysis
-                    stack = None;
-                }
-
+                instructions[current].flow = branch(imm32, bytes.len());
+                instructions[current].conditional = true;
                 bs.push(imm32);
-            } else if v7
+            } else if v8m_base
                 && matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b10_x_1_x_xxxxxxxxxxx")
             {
@@ -512,11 +710,7 @@ pub fn analyze(
                 // accordingly
                 imm32 += 2 * i + 4;
 
-                if imm32 >= 0 && (imm32 as usize) < bytes.len() {
-                    // this is an `if` or `loop`; give up the stack usage analysis
-                    stack = None;
-                }
-
+                instructions[current].flow = branch(imm32, bytes.len());
                 bs.push(imm32);
             } else if matches(first, "0b11110_x_xxxxxxxxxx")
                 && matches(second, "0b11_x_1_x_xxxxxxxxxxx")
@@ -551,9 +745,232 @@ pub fn analyze(
         }
     }
 
+    let stack = max_depth(&instructions);
+    if let Some(stack) = stack {
+        // the scan above also sees unreachable code (e.g. after `b .` or at unreferenced labels in
+        // `global_asm!`); only the instructions in the control flow graph count
+        modifies_sp = stack != 0;
+    }
+
     (bls, bs, indirect, modifies_sp, stack)
 }
 
+// the effect of an instruction on the SP and on the control flow
+#[derive(Clone, Debug)]
+struct Instruction {
+    // offset from the start of the subroutine, in bytes
+    offset: u32,
+    // 2 or 4 bytes
+    size: u32,
+    // number of bytes allocated (positive) or freed (negative) on the stack; `None` if the SP is
+    // set to a value we can't track
+    sp: Option<i32>,
+    flow: Flow,
+    // conditional branches and instructions in IT blocks; if the condition doesn't hold the
+    // instruction has no effect
+    conditional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Flow {
+    // continues with the next instruction
+    Next,
+    // branch to another instruction of the subroutine
+    Jump(u32),
+    // branch to one of several instructions of the subroutine (e.g. `tbb`)
+    Table(Vec<u32>),
+    // leaves the subroutine (return or tail call); the SP must be back to its initial value
+    Return,
+    // doesn't continue (e.g. `udf`)
+    Stop,
+    // control flow we don't understand (e.g. a jump table)
+    Unknown,
+}
+
+// a branch to `target`, an offset relative to the start of the subroutine; branches that leave the
+// subroutine are tail calls
+fn branch(target: i32, len: usize) -> Flow {
+    if target >= 0 && (target as usize) < len {
+        Flow::Jump(target as u32)
+    } else {
+        Flow::Return
+    }
+}
+
+// the data section (`$d` tag) that starts at `start`, or at the next word boundary; returns its
+// bounds as offsets relative to `address`, the start of the subroutine
+fn data_after(bytes: &[u8], address: u32, tags: &[(u32, Tag)], start: u32) -> Option<(u32, u32)> {
+    let aligned = ((address + start + 3) & !3) - address;
+
+    [start, aligned].iter().find_map(|start| {
+        let needle = tags
+            .binary_search_by(|(addr, _)| addr.cmp(&(address + start)))
+            .ok()?;
+
+        if tags[needle].1 != Tag::Data {
+            return None;
+        }
+
+        let len = bytes.len() as u32;
+        let end = tags
+            .get(needle + 1)
+            .map(|tag| tag.0 - address)
+            .unwrap_or(len);
+        Some((*start, cmp::min(end, len)))
+    })
+}
+
+// targets of a table branch (`tbb [pc, rm]` or `tbh [pc, rm, lsl #1]`); `start` is the offset of
+// the table, which immediately follows the instruction
+fn table_branch(bytes: &[u8], address: u32, tags: &[(u32, Tag)], start: u32, half: bool) -> Flow {
+    let (table_start, table_end) = match data_after(bytes, address, tags, start) {
+        Some(bounds) if bounds.0 == start => bounds,
+        _ => return Flow::Unknown,
+    };
+
+    let table = &bytes[table_start as usize..table_end as usize];
+    let entries = if half {
+        table
+            .chunks_exact(2)
+            .map(|entry| u32::from(entry[0]) | (u32::from(entry[1]) << 8))
+            .collect::<Vec<_>>()
+    } else {
+        table.iter().map(|entry| u32::from(*entry)).collect()
+    };
+
+    let mut targets = vec![];
+    for entry in entries {
+        // offsets are relative to the PC, which is the start of the table
+        let target = start + 2 * entry;
+
+        if target >= table_start && target < table_end {
+            // padding at the end of the table
+            continue;
+        }
+
+        if target as usize >= bytes.len() {
+            return Flow::Unknown;
+        }
+
+        targets.push(target);
+    }
+
+    if targets.is_empty() {
+        Flow::Unknown
+    } else {
+        Flow::Table(targets)
+    }
+}
+
+// targets of a computed jump (e.g. `mov pc, r0`) through a table of addresses that follows the
+// jump instruction; `start` is the offset of the next instruction
+fn jump_table(bytes: &[u8], address: u32, tags: &[(u32, Tag)], start: u32) -> Flow {
+    let (table_start, table_end) = match data_after(bytes, address, tags, start) {
+        Some(bounds) => bounds,
+        None => return Flow::Unknown,
+    };
+
+    let mut targets = vec![];
+    for entry in bytes[table_start as usize..table_end as usize].chunks_exact(4) {
+        let entry = u32::from(entry[0])
+            | (u32::from(entry[1]) << 8)
+            | (u32::from(entry[2]) << 16)
+            | (u32::from(entry[3]) << 24);
+
+        // clear the thumb bit
+        let target = (entry & !1).wrapping_sub(address);
+
+        if target as usize >= bytes.len() || (target >= table_start && target < table_end) {
+            // not a jump table or not one we understand
+            return Flow::Unknown;
+        }
+
+        targets.push(target);
+    }
+
+    if targets.is_empty() {
+        Flow::Unknown
+    } else {
+        Flow::Table(targets)
+    }
+}
+
+// walks the control flow graph of a subroutine and returns the maximum stack depth reached
+//
+// returns `None` if the depth at some instruction depends on the path taken to reach it, if a path
+// leaves the subroutine with an unbalanced stack or if we can't follow the control flow or the SP
+fn max_depth(instructions: &[Instruction]) -> Option<u64> {
+    if instructions.is_empty() {
+        return Some(0);
+    }
+
+    // stack depth on entry to each instruction
+    let mut depths = vec![None; instructions.len()];
+    let mut max = 0;
+
+    let mut worklist = vec![(0, 0)];
+    while let Some((i, depth)) = worklist.pop() {
+        match depths[i] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => return None,
+            None => depths[i] = Some(depth),
+        }
+
+        let instr = &instructions[i];
+        // NOTE falling off the end of the subroutine is fine; it happens after calls to
+        // divergent functions
+        let next = instructions
+            .get(i + 1)
+            .filter(|next| next.offset == instr.offset + instr.size)
+            .map(|_| i + 1);
+
+        if instr.conditional {
+            // the condition doesn't hold
+            worklist.extend(next.map(|next| (next, depth)));
+        }
+
+        let depth = depth + instr.sp?;
+        if depth < 0 {
+            // popped more than what was pushed
+            return None;
+        }
+        max = cmp::max(max, depth);
+
+        match &instr.flow {
+            Flow::Next => worklist.extend(next.map(|next| (next, depth))),
+
+            Flow::Jump(target) => {
+                // NOTE this fails if the target is in the middle of an instruction or in data
+                let j = instructions
+                    .binary_search_by(|instr| instr.offset.cmp(target))
+                    .ok()?;
+                worklist.push((j, depth));
+            }
+
+            Flow::Table(targets) => {
+                for target in targets {
+                    let j = instructions
+                        .binary_search_by(|instr| instr.offset.cmp(target))
+                        .ok()?;
+                    worklist.push((j, depth));
+                }
+            }
+
+            Flow::Return => {
+                if depth != 0 {
+                    return None;
+                }
+            }
+
+            Flow::Stop => {}
+
+            Flow::Unknown => return None,
+        }
+    }
+
+    Some(max as u64)
+}
+
 fn matches(bytes: &[u8], pattern: &str) -> bool {
     assert!(pattern.starts_with("0b"));
 
@@ -603,6 +1020,19 @@ fn thumb_expand_imm(imm12: u16) -> u32 {
     }
 }
 
+/// The M-profile architecture the subroutine was compiled for
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Arch {
+    // `thumbv6m-none-eabi`
+    V6M,
+    // `thumbv7m-none-eabi`, `thumbv7em-none-eabi(hf)`
+    V7M,
+    // `thumbv8m.base-none-eabi`
+    V8MBase,
+    // `thumbv8m.main-none-eabi(hf)`
+    V8MMain,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Tag {
     // symbol with name `$d.123` used as a tag
@@ -614,26 +1044,28 @@ pub enum Tag {
 
 #[cfg(test)]
 mod tests {
+    use super::Arch;
+
     #[test]
     fn sanity() {
         assert_eq!(
-            super::analyze(&[0xff, 0xf7, 0xe4, 0xfe], 0, false, &[]).0,
+            super::analyze(&[0xff, 0xf7, 0xe4, 0xfe], 0, Arch::V6M, &[]).0,
             vec![-568 + 4]
         );
 
         assert_eq!(
-            super::analyze(&[0x00, 0xf0, 0x2a, 0xfa], 0, false, &[]).0,
+            super::analyze(&[0x00, 0xf0, 0x2a, 0xfa], 0, Arch::V6M, &[]).0,
             vec![1108 + 4]
         );
 
         assert_eq!(
-            super::analyze(&[0x03, 0xe2], 0, false, &[]).1,
+            super::analyze(&[0x03, 0xe2], 0, Arch::V6M, &[]).1,
             vec![1030 + 4]
         );
 
         // UDF
         assert_eq!(
-            super::analyze(&[0xfe, 0xde], 0, true, &[]),
+            super::analyze(&[0xfe, 0xde], 0, Arch::V7M, &[]),
             (vec![], vec![], false, false, Some(0))
         );
     }
@@ -641,33 +1073,187 @@ mod tests {
     #[test]
     fn modifies_sp() {
         // bf00            nop
-        let nop = super::analyze(&[0x00, 0xbf], 0, false, &[]);
+        let nop = super::analyze(&[0x00, 0xbf], 0, Arch::V6M, &[]);
         assert!(!nop.3);
         assert_eq!(nop.4, Some(0));
 
         // b081            sub     sp, #4
-        let sub = super::analyze(&[0x81, 0xb0], 0, false, &[]);
+        let sub = super::analyze(&[0x81, 0xb0], 0, Arch::V6M, &[]);
         assert!(sub.3);
         assert_eq!(sub.4, Some(4));
 
         // b580            push    {r7, lr}
-        let push = super::analyze(&[0x80, 0xb5], 0, false, &[]);
+        let push = super::analyze(&[0x80, 0xb5], 0, Arch::V6M, &[]);
         assert!(push.3);
         assert_eq!(push.4, Some(8));
 
         // e92d 41f0       stmdb   sp!, {r4, r5, r6, r7, r8, lr}
-        let stmdb = super::analyze(&[0x2d, 0xe9, 0xf0, 0x41], 0, true, &[]);
+        let stmdb = super::analyze(&[0x2d, 0xe9, 0xf0, 0x41], 0, Arch::V7M, &[]);
         assert!(stmdb.3);
         assert_eq!(stmdb.4, Some(24));
 
         // ed2d 8b02       vpush   {d8}
-        let vpush = super::analyze(&[0x2d, 0xed, 0x02, 0x8b], 0, true, &[]);
+        let vpush = super::analyze(&[0x2d, 0xed, 0x02, 0x8b], 0, Arch::V7M, &[]);
         assert!(vpush.3);
         assert_eq!(vpush.4, Some(8));
 
         // f5ad 7d02       sub.w   sp, sp, #520    ; 0x208
-        let subw = super::analyze(&[0xad, 0xf5, 0x02, 0x7d], 0, true, &[]);
+        let subw = super::analyze(&[0xad, 0xf5, 0x02, 0x7d], 0, Arch::V7M, &[]);
         assert!(subw.3);
         assert_eq!(subw.4, Some(520));
     }
+
+    #[test]
+    fn control_flow() {
+        // b510            push    {r4, lr}
+        // b082            sub     sp, #8
+        // 3801            subs    r0, #1
+        // d1fd            bne.n   4
+        // b002            add     sp, #8
+        // bd10            pop     {r4, pc}
+        let counter = super::analyze(
+            &[
+                0x10, 0xb5, 0x82, 0xb0, 0x01, 0x38, 0xfd, 0xd1, 0x02, 0xb0, 0x10, 0xbd,
+            ],
+            0,
+            Arch::V6M,
+            &[],
+        );
+        assert_eq!(counter.1, vec![4]);
+        assert!(counter.3);
+        assert_eq!(counter.4, Some(16));
+
+        // b580            push    {r7, lr}
+        // 2800            cmp     r0, #0
+        // bf08            it      eq
+        // bd80            popeq   {r7, pc}
+        // b082            sub     sp, #8
+        // b002            add     sp, #8
+        // bd80            pop     {r7, pc}
+        let early_return = super::analyze(
+            &[
+                0x80, 0xb5, 0x00, 0x28, 0x08, 0xbf, 0x80, 0xbd, 0x82, 0xb0, 0x02, 0xb0, 0x80, 0xbd,
+            ],
+            0,
+            Arch::V7M,
+            &[],
+        );
+        assert_eq!(early_return.4, Some(16));
+
+        // b580            push    {r7, lr}
+        // 4770            bx      lr
+        let unbalanced = super::analyze(&[0x80, 0xb5, 0x70, 0x47], 0, Arch::V6M, &[]);
+        assert!(unbalanced.3);
+        assert_eq!(unbalanced.4, None);
+
+        // e8df f000       tbb     [pc, r0]
+        let jump_table = super::analyze(&[0xdf, 0xe8, 0x00, 0xf0], 0, Arch::V7M, &[]);
+        assert!(!jump_table.3);
+        assert_eq!(jump_table.4, None);
+
+        // e7fe            b.n     0
+        // b580            push    {r7, lr}
+        let dead_code = super::analyze(&[0xfe, 0xe7, 0x80, 0xb5], 0, Arch::V6M, &[]);
+        assert!(!dead_code.3);
+        assert_eq!(dead_code.4, Some(0));
+    }
+
+    #[test]
+    fn v8m() {
+        // e97f e97f       sg
+        // f000 b880       b.w     #0x108
+        assert_eq!(
+            super::analyze(
+                &[0x7f, 0xe9, 0x7f, 0xe9, 0x00, 0xf0, 0x80, 0xb8],
+                0,
+                Arch::V8MMain,
+                &[]
+            ),
+            (vec![], vec![0x108], false, false, Some(0))
+        );
+
+        // e841 f000       tt      r0, r1
+        // 4774            bxns    lr
+        assert_eq!(
+            super::analyze(&[0x41, 0xe8, 0x00, 0xf0, 0x74, 0x47], 0, Arch::V8MMain, &[]),
+            (vec![], vec![], false, false, Some(0))
+        );
+
+        // b580            push    {r7, lr}
+        // b110            cbz     r0, #0xa
+        // f241 2134       movw    r1, #0x1234
+        // 478c            blxns   r1
+        // bd80            pop     {r7, pc}
+        assert_eq!(
+            super::analyze(
+                &[0x80, 0xb5, 0x10, 0xb1, 0x41, 0xf2, 0x34, 0x21, 0x8c, 0x47, 0x80, 0xbd],
+                0,
+                Arch::V8MBase,
+                &[]
+            ),
+            (vec![], vec![], true, true, Some(8))
+        );
+
+        // defe            udf     #254
+        assert_eq!(
+            super::analyze(&[0xfe, 0xde], 0, Arch::V8MBase, &[]),
+            (vec![], vec![], false, false, Some(0))
+        );
+        assert_eq!(
+            super::analyze(&[0xfe, 0xde], 0, Arch::V6M, &[]),
+            (vec![], vec![], false, false, Some(0))
+        );
+    }
+
+    #[test]
+    fn jump_tables() {
+        use super::Tag;
+
+        // b580            push    {r7, lr}
+        // e8df f000       tbb     [pc, r0]
+        // $d
+        // 01 02
+        // $t
+        // bd80            pop     {r7, pc}
+        // b082            sub     sp, #8
+        // b002            add     sp, #8
+        // bd80            pop     {r7, pc}
+        let tbb = super::analyze(
+            &[
+                0x80, 0xb5, 0xdf, 0xe8, 0x00, 0xf0, 0x01, 0x02, 0x80, 0xbd, 0x82, 0xb0, 0x02, 0xb0,
+                0x80, 0xbd,
+            ],
+            0,
+            Arch::V7M,
+            &[(6, Tag::Data), (8, Tag::Thumb)],
+        );
+        assert_eq!(tbb, (vec![], vec![], false, true, Some(16)));
+
+        // 1000: b580            push    {r7, lr}
+        // 1002: 5808            ldr     r0, [r1, r0]
+        // 1004: 4687            mov     pc, r0
+        // 1006: bf00            nop
+        // $d
+        // 1008: 00001011 00001015
+        // $t
+        // 1010: bd80            pop     {r7, pc}
+        // 1012: bf00            nop
+        // 1014: b082            sub     sp, #8
+        // 1016: b002            add     sp, #8
+        // 1018: bd80            pop     {r7, pc}
+        let mov_pc = super::analyze(
+            &[
+                0x80, 0xb5, 0x08, 0x58, 0x87, 0x46, 0x00, 0xbf, 0x11, 0x10, 0x00, 0x00, 0x15, 0x10,
+                0x00, 0x00, 0x80, 0xbd, 0x00, 0xbf, 0x82, 0xb0, 0x02, 0xb0, 0x80, 0xbd,
+            ],
+            0x1000,
+            Arch::V6M,
+            &[(0x1008, Tag::Data), (0x1010, Tag::Thumb)],
+        );
+        assert_eq!(mov_pc, (vec![], vec![], false, true, Some(16)));
+
+        // without the tags the table can't be found
+        let unknown = super::analyze(&[0x80, 0xb5, 0x87, 0x46], 0x1000, Arch::V6M, &[]);
+        assert_eq!(unknown.4, None);
+    }
 }
@@ -170,7 +170,7 @@ fn channel_is_nightly() -> bool {
 fn call_stack(ex: &str) -> String {
     String::from_utf8(
         Command::new("cargo")
-            .args(&["call-stack", "--example", ex])
+            .args(["call-stack", "--example", ex])
             .current_dir(env::current_dir().unwrap().join("cortex-m-examples"))
             .output()
             .unwrap()